@@ -1,4 +1,6 @@
-use crate::processor::CompletionGenerator;
+use crate::config::Example;
+use crate::image::Image;
+use crate::processor::{CompletionGenerator, CompletionOptions};
 use crate::{Error, Result};
 use async_stream::stream;
 use futures::{stream::LocalBoxStream, StreamExt};
@@ -22,38 +24,66 @@ impl GenAI {
 impl CompletionGenerator for GenAI {
     async fn generate_completion(
         &self,
-        model: &str,
-        _temperature: f32,
+        options: &CompletionOptions,
         prompt: &str,
+        examples: &[Example],
         input: &str,
+        images: &[Image],
     ) -> crate::Result<String> {
-        let req = ChatRequest::new(vec![ChatMessage::system(prompt), ChatMessage::user(input)]);
-        let resp = self.client.exec_chat(model, req.clone(), None).await?;
+        if !images.is_empty() {
+            return Err(Error::GenAIImageInput);
+        }
+
+        let req = ChatRequest::new(messages(prompt, examples, input));
+        let resp = self
+            .client
+            .exec_chat(&options.model, req.clone(), None)
+            .await?;
         resp.content.ok_or(Error::EmptyResponse)
     }
 
     async fn stream_completion(
         &self,
-        model: &str,
-        _temperature: f32,
+        options: &CompletionOptions,
         prompt: &str,
+        examples: &[Example],
         input: &str,
-    ) -> Result<LocalBoxStream<String>> {
-        let req = ChatRequest::new(vec![ChatMessage::system(prompt), ChatMessage::user(input)]);
+        images: &[Image],
+    ) -> Result<LocalBoxStream<'_, Result<String>>> {
+        if !images.is_empty() {
+            return Err(Error::GenAIImageInput);
+        }
+
+        let req = ChatRequest::new(messages(prompt, examples, input));
         let resp = self
             .client
-            .exec_chat_stream(model, req.clone(), None)
+            .exec_chat_stream(&options.model, req.clone(), None)
             .await?;
 
         let async_stream = stream! {
-                let mut stream = resp.stream;
-                while let Some(Ok(stream_event)) = stream.next().await {
-                    if let ChatStreamEvent::Chunk(StreamChunk { content }) = stream_event {
-                        yield content;
+            let mut stream = resp.stream;
+            while let Some(stream_event) = stream.next().await {
+                match stream_event {
+                    Ok(ChatStreamEvent::Chunk(StreamChunk { content })) => yield Ok(content),
+                    Ok(_) => {}
+                    Err(e) => {
+                        yield Err(e.into());
+                        break;
                     }
-                };
+                }
+            }
         };
 
         Ok(Box::pin(async_stream))
     }
 }
+
+fn messages(prompt: &str, examples: &[Example], input: &str) -> Vec<ChatMessage> {
+    let mut messages = vec![ChatMessage::system(prompt)];
+    for example in examples {
+        messages.push(ChatMessage::user(&example.input));
+        messages.push(ChatMessage::assistant(&example.output));
+    }
+    messages.push(ChatMessage::user(input));
+    messages
+}
@@ -0,0 +1,46 @@
+use crate::config::Example;
+use crate::image::Image;
+use crate::processor::{CompletionGenerator, CompletionOptions};
+use crate::Result;
+use futures::stream::{self, LocalBoxStream, StreamExt};
+
+/// A backend that doesn't call any model. It answers with a fixed response,
+/// like the fixture output of a prompt test, or echoes the input otherwise.
+pub struct Mock {
+    response: Option<String>,
+}
+
+impl Mock {
+    pub fn new(response: Option<String>) -> Self {
+        Self { response }
+    }
+
+    fn answer(&self, input: &str) -> String {
+        self.response.clone().unwrap_or_else(|| input.to_string())
+    }
+}
+
+impl CompletionGenerator for Mock {
+    async fn generate_completion(
+        &self,
+        _options: &CompletionOptions,
+        _prompt: &str,
+        _examples: &[Example],
+        input: &str,
+        _images: &[Image],
+    ) -> Result<String> {
+        Ok(self.answer(input))
+    }
+
+    async fn stream_completion(
+        &self,
+        _options: &CompletionOptions,
+        _prompt: &str,
+        _examples: &[Example],
+        input: &str,
+        _images: &[Image],
+    ) -> Result<LocalBoxStream<'_, Result<String>>> {
+        let answer = self.answer(input);
+        Ok(stream::once(async move { Ok(answer) }).boxed_local())
+    }
+}
@@ -1,2 +1,102 @@
 pub mod genai;
+pub mod mock;
 pub mod openai;
+
+use self::{genai::GenAI, mock::Mock, openai::OpenAI};
+use crate::{
+    config::Example,
+    image::Image,
+    processor::{CompletionGenerator, CompletionOptions},
+    Result,
+};
+use ::genai::adapter::AdapterKind;
+use clap::ValueEnum;
+use futures::stream::LocalBoxStream;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Any provider supported by genai, picked from the model name
+    #[value(name = "genai")]
+    GenAI,
+    /// The OpenAI API, required for image input
+    #[value(name = "openai")]
+    OpenAI,
+    /// No model at all: answers with test fixtures or echoes the input
+    #[value(name = "mock")]
+    Mock,
+}
+
+impl Backend {
+    pub fn generator(self) -> Result<Generator> {
+        Ok(match self {
+            Backend::GenAI => Generator::GenAI(GenAI::new()),
+            Backend::OpenAI => Generator::OpenAI(OpenAI::new()?),
+            Backend::Mock => Generator::Mock(Mock::new(None)),
+        })
+    }
+}
+
+/// Whether the model is served by OpenAI, using the same rules genai uses
+/// to pick a provider from the model name.
+pub fn is_openai_model(model: &str) -> bool {
+    matches!(AdapterKind::from_model(model), Ok(AdapterKind::OpenAI))
+}
+
+/// A completion generator for whichever backend was selected at runtime.
+pub enum Generator {
+    GenAI(GenAI),
+    OpenAI(OpenAI),
+    Mock(Mock),
+}
+
+impl CompletionGenerator for Generator {
+    async fn generate_completion(
+        &self,
+        options: &CompletionOptions,
+        prompt: &str,
+        examples: &[Example],
+        input: &str,
+        images: &[Image],
+    ) -> Result<String> {
+        match self {
+            Generator::GenAI(g) => {
+                g.generate_completion(options, prompt, examples, input, images)
+                    .await
+            }
+            Generator::OpenAI(g) => {
+                g.generate_completion(options, prompt, examples, input, images)
+                    .await
+            }
+            Generator::Mock(g) => {
+                g.generate_completion(options, prompt, examples, input, images)
+                    .await
+            }
+        }
+    }
+
+    async fn stream_completion(
+        &self,
+        options: &CompletionOptions,
+        prompt: &str,
+        examples: &[Example],
+        input: &str,
+        images: &[Image],
+    ) -> Result<LocalBoxStream<'_, Result<String>>> {
+        match self {
+            Generator::GenAI(g) => {
+                g.stream_completion(options, prompt, examples, input, images)
+                    .await
+            }
+            Generator::OpenAI(g) => {
+                g.stream_completion(options, prompt, examples, input, images)
+                    .await
+            }
+            Generator::Mock(g) => {
+                g.stream_completion(options, prompt, examples, input, images)
+                    .await
+            }
+        }
+    }
+}
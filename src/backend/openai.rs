@@ -1,10 +1,16 @@
-use crate::processor::CompletionGenerator;
+use crate::config::Example;
+use crate::image::Image;
+use crate::processor::{CompletionGenerator, CompletionOptions};
 use crate::{Error, Result};
 use async_openai::{
     config::OpenAIConfig,
     types::{
-        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
-        CreateChatCompletionRequestArgs,
+        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+        ChatCompletionRequestMessageContentPart, ChatCompletionRequestMessageContentPartImageArgs,
+        ChatCompletionRequestMessageContentPartTextArgs, ChatCompletionRequestSystemMessageArgs,
+        ChatCompletionRequestUserMessageArgs, ChatCompletionRequestUserMessageContent,
+        ChatCompletionResponseFormat, ChatCompletionResponseFormatType,
+        CreateChatCompletionRequestArgs, CreateChatCompletionResponse, ImageUrlArgs,
     },
     Client,
 };
@@ -30,69 +36,64 @@ impl OpenAI {
 impl CompletionGenerator for OpenAI {
     async fn generate_completion(
         &self,
-        model: &str,
-        temperature: f32,
+        options: &CompletionOptions,
         prompt: &str,
+        examples: &[Example],
         input: &str,
+        images: &[Image],
     ) -> Result<String> {
-        let messages = [
-            ChatCompletionRequestSystemMessageArgs::default()
-                .content(prompt)
-                .build()?
-                .into(),
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(input)
-                .build()?
-                .into(),
-        ];
+        let messages = messages(prompt, examples, input, images)?;
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(model)
-            .temperature(temperature)
-            .messages(messages)
-            .build()?;
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request
+            .model(&options.model)
+            .temperature(options.temperature)
+            .messages(messages);
+        if let Some(max_tokens) = options.max_tokens {
+            request.max_tokens(u16::try_from(max_tokens).unwrap_or(u16::MAX));
+        }
+        if options.json_object {
+            request.response_format(ChatCompletionResponseFormat {
+                r#type: ChatCompletionResponseFormatType::JsonObject,
+            });
+        }
+        let request = request.build()?;
 
         let response = self.client.chat().create(request).await?;
-
-        Ok(response
-            .choices
-            .first()
-            .unwrap()
-            .message
-            .content
-            .clone()
-            .unwrap())
+        answer(response)
     }
 
     async fn stream_completion(
         &self,
-        model: &str,
-        temperature: f32,
+        options: &CompletionOptions,
         prompt: &str,
+        examples: &[Example],
         input: &str,
-    ) -> Result<LocalBoxStream<String>> {
-        let messages = [
-            ChatCompletionRequestSystemMessageArgs::default()
-                .content(prompt)
-                .build()?
-                .into(),
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(input)
-                .build()?
-                .into(),
-        ];
+        images: &[Image],
+    ) -> Result<LocalBoxStream<'_, Result<String>>> {
+        let messages = messages(prompt, examples, input, images)?;
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(model)
-            .temperature(temperature)
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request
+            .model(&options.model)
+            .temperature(options.temperature)
             .messages(messages)
-            .stream(true)
-            .build()?;
+            .stream(true);
+        if let Some(max_tokens) = options.max_tokens {
+            request.max_tokens(u16::try_from(max_tokens).unwrap_or(u16::MAX));
+        }
+        if options.json_object {
+            request.response_format(ChatCompletionResponseFormat {
+                r#type: ChatCompletionResponseFormatType::JsonObject,
+            });
+        }
+        let request = request.build()?;
 
         let async_stream = stream! {
             let st = match self.client.chat().create_stream(request).await {
                 Ok(response) => response,
-                Err(_) => {
+                Err(e) => {
+                    yield Err(e.into());
                     return;
                 }
             };
@@ -100,9 +101,12 @@ impl CompletionGenerator for OpenAI {
             for await response in st {
                 match response {
                     Ok(response) => {
-                        yield response.choices[0].delta.content.clone().unwrap_or_default();
+                        if let Some(choice) = response.choices.into_iter().next() {
+                            yield Ok(choice.delta.content.unwrap_or_default());
+                        }
                     },
-                    Err(_) => {
+                    Err(e) => {
+                        yield Err(e.into());
                         break;
                     }
                 };
@@ -112,3 +116,104 @@ impl CompletionGenerator for OpenAI {
         Ok(Box::pin(async_stream))
     }
 }
+
+/// The text of the first choice. Providers can send no choices, or one
+/// without content when a filter stopped the model.
+fn answer(response: CreateChatCompletionResponse) -> Result<String> {
+    response
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.message.content)
+        .ok_or(Error::EmptyResponse)
+}
+
+fn messages(
+    prompt: &str,
+    examples: &[Example],
+    input: &str,
+    images: &[Image],
+) -> Result<Vec<ChatCompletionRequestMessage>> {
+    let mut messages = vec![ChatCompletionRequestSystemMessageArgs::default()
+        .content(prompt)
+        .build()?
+        .into()];
+
+    for example in examples {
+        messages.push(
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(example.input.as_str())
+                .build()?
+                .into(),
+        );
+        messages.push(
+            ChatCompletionRequestAssistantMessageArgs::default()
+                .content(example.output.as_str())
+                .build()?
+                .into(),
+        );
+    }
+
+    messages.push(
+        ChatCompletionRequestUserMessageArgs::default()
+            .content(user_content(input, images)?)
+            .build()?
+            .into(),
+    );
+    Ok(messages)
+}
+
+fn user_content(input: &str, images: &[Image]) -> Result<ChatCompletionRequestUserMessageContent> {
+    if images.is_empty() {
+        return Ok(input.into());
+    }
+
+    let mut parts: Vec<ChatCompletionRequestMessageContentPart> =
+        vec![ChatCompletionRequestMessageContentPartTextArgs::default()
+            .text(input)
+            .build()?
+            .into()];
+
+    for image in images {
+        let part = ChatCompletionRequestMessageContentPartImageArgs::default()
+            .image_url(ImageUrlArgs::default().url(image.to_data_url()).build()?)
+            .build()?;
+        parts.push(ChatCompletionRequestMessageContentPart::Image(part));
+    }
+
+    Ok(ChatCompletionRequestUserMessageContent::Array(parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(choices: &str) -> CreateChatCompletionResponse {
+        serde_json::from_str(&format!(
+            r#"{{"id": "chatcmpl-1", "object": "chat.completion", "created": 0, "model": "gpt-4o", "choices": {}}}"#,
+            choices
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn answer_is_the_first_choice() {
+        let response = response(
+            r#"[{"index": 0, "message": {"role": "assistant", "content": "ls -la"}, "finish_reason": "stop"}]"#,
+        );
+        assert_eq!(answer(response).unwrap(), "ls -la");
+    }
+
+    #[test]
+    fn empty_choices_are_an_empty_response() {
+        assert!(matches!(answer(response("[]")), Err(Error::EmptyResponse)));
+    }
+
+    #[test]
+    fn choice_without_content_is_an_empty_response() {
+        let response = response(
+            r#"[{"index": 0, "message": {"role": "assistant", "content": null}, "finish_reason": "content_filter"}]"#,
+        );
+        assert!(matches!(answer(response), Err(Error::EmptyResponse)));
+    }
+}
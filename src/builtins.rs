@@ -0,0 +1,59 @@
+use crate::system;
+use std::{env, process::Command};
+
+const ENV_PREFIX: &str = "env.";
+const NAMES: [&str; 8] = [
+    "shell",
+    "os",
+    "cwd",
+    "date",
+    "user",
+    "hostname",
+    "git_branch",
+    "input_lines",
+];
+
+/// Whether `name` is a variable that every prompt gets for free.
+pub fn is_builtin(name: &str) -> bool {
+    NAMES.contains(&name) || name.starts_with(ENV_PREFIX)
+}
+
+/// Resolves a built-in variable. Values that can't be determined, like the
+/// git branch outside a repository, resolve to an empty string.
+pub fn resolve(name: &str, input: &str) -> Option<String> {
+    if let Some(var) = name.strip_prefix(ENV_PREFIX) {
+        return Some(env::var(var).unwrap_or_default());
+    }
+
+    let value = match name {
+        "shell" => system::get_current_shell().unwrap_or_default(),
+        "os" => env::consts::OS.to_string(),
+        "cwd" => env::current_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default(),
+        "date" => chrono::Local::now().format("%Y-%m-%d").to_string(),
+        "user" => env::var("USER")
+            .or_else(|_| env::var("USERNAME"))
+            .unwrap_or_default(),
+        "hostname" => env::var("HOSTNAME")
+            .ok()
+            .or_else(|| command_output("hostname", &[]))
+            .unwrap_or_default(),
+        "git_branch" => {
+            command_output("git", &["rev-parse", "--abbrev-ref", "HEAD"]).unwrap_or_default()
+        }
+        "input_lines" => input.lines().count().to_string(),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
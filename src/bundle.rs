@@ -0,0 +1,171 @@
+use crate::config::{read_prompt, read_prompt_dir, Prompt};
+use crate::{template, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+const VERSION: u32 = 1;
+
+/// A set of prompts shared as a single YAML file. Each prompt is kept as the
+/// Markdown document it's stored as, so nothing is lost on the way.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Bundle {
+    pub version: u32,
+    pub prompts: Vec<BundleEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundleEntry {
+    pub name: String,
+    pub document: String,
+}
+
+impl Bundle {
+    pub fn new(prompts: &[Prompt]) -> Result<Self> {
+        let prompts = prompts
+            .iter()
+            .map(|prompt| {
+                Ok(BundleEntry {
+                    name: prompt.name.clone(),
+                    document: prompt.to_markdown()?,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            version: VERSION,
+            prompts,
+        })
+    }
+
+    pub fn to_yaml(&self) -> Result<String> {
+        serde_yaml::to_string(self).map_err(|e| Error::InvalidBundle {
+            path: "-".to_string(),
+            reason: e.to_string(),
+        })
+    }
+}
+
+/// Loads the prompts to import from a bundle, a single prompt file, or a
+/// directory of prompt files. `file://` URLs are accepted for local paths.
+pub fn load_prompts(source: &str) -> Result<Vec<Prompt>> {
+    let path = Path::new(source.strip_prefix("file://").unwrap_or(source));
+    let invalid = |reason: String| Error::InvalidBundle {
+        path: path.display().to_string(),
+        reason,
+    };
+
+    let prompts = if path.is_dir() {
+        read_prompt_dir(path)?
+    } else if matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("md" | "json")
+    ) {
+        vec![read_prompt(path)?]
+    } else {
+        let bundle: Bundle =
+            serde_yaml::from_str(&fs::read_to_string(path)?).map_err(|e| invalid(e.to_string()))?;
+        if bundle.version > VERSION {
+            Err(invalid(format!("unsupported version {}", bundle.version)))?;
+        }
+
+        bundle
+            .prompts
+            .into_iter()
+            .map(|entry| Prompt::from_markdown(&entry.name, &entry.document))
+            .collect::<Result<_>>()?
+    };
+
+    // Names become file names, so they can't point outside the prompts directory.
+    if let Some(prompt) = prompts
+        .iter()
+        .find(|p| !template::is_valid_prompt_name(&p.name))
+    {
+        Err(invalid(format!("invalid prompt name '{}'", prompt.name)))?;
+    }
+
+    Ok(prompts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf};
+
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("shelldon-bundle-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn prompt(name: &str) -> Prompt {
+        let document = "---\ndescription: Translate text\nvariables:\n  to: french\n---\n\nTranslate to {to}.\n";
+        Prompt::from_markdown(name, document).unwrap()
+    }
+
+    fn invalid_reason(result: Result<Vec<Prompt>>) -> String {
+        match result {
+            Err(Error::InvalidBundle { reason, .. }) => reason,
+            other => panic!("expected an invalid bundle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn round_trips_prompts() {
+        let path = temp_dir("round-trip").join("prompts.yaml");
+        let yaml = Bundle::new(&[prompt("translator"), prompt("other")])
+            .unwrap()
+            .to_yaml()
+            .unwrap();
+        fs::write(&path, yaml).unwrap();
+
+        let prompts = load_prompts(&format!("file://{}", path.display())).unwrap();
+        let names: Vec<_> = prompts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["translator", "other"]);
+        assert_eq!(prompts[0].description.as_deref(), Some("Translate text"));
+        assert_eq!(prompts[0].content.trim(), "Translate to {to}.");
+    }
+
+    #[test]
+    fn loads_a_single_prompt_file() {
+        let path = temp_dir("single").join("translator.md");
+        fs::write(&path, prompt("translator").to_markdown().unwrap()).unwrap();
+
+        let prompts = load_prompts(path.to_str().unwrap()).unwrap();
+        assert_eq!(prompts.len(), 1);
+        assert_eq!(prompts[0].name, "translator");
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let path = temp_dir("version").join("prompts.yaml");
+        fs::write(&path, "version: 2\nprompts: []\n").unwrap();
+
+        let reason = invalid_reason(load_prompts(path.to_str().unwrap()));
+        assert_eq!(reason, "unsupported version 2");
+    }
+
+    #[test]
+    fn rejects_names_outside_the_prompts_dir() {
+        let path = temp_dir("names").join("prompts.yaml");
+        let yaml = Bundle::new(&[prompt("../evil")])
+            .unwrap()
+            .to_yaml()
+            .unwrap();
+        fs::write(&path, yaml).unwrap();
+
+        let reason = invalid_reason(load_prompts(path.to_str().unwrap()));
+        assert_eq!(reason, "invalid prompt name '../evil'");
+    }
+
+    #[test]
+    fn rejects_malformed_yaml() {
+        let path = temp_dir("malformed").join("prompts.yaml");
+        fs::write(&path, "prompts: [").unwrap();
+
+        assert!(matches!(
+            load_prompts(path.to_str().unwrap()),
+            Err(Error::InvalidBundle { .. })
+        ));
+    }
+}
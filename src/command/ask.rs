@@ -1,49 +1,497 @@
-use super::CommonArgs;
+use super::{estimate_tokens, CommonArgs, OutputFormat, Usage};
 use crate::{
-    backend::genai::GenAI,
-    command::{parse_prompt, read_input},
-    config::Config,
-    processor::CompletionProcessor,
-    system, Result,
+    backend::{is_openai_model, Backend, Generator},
+    command::{parse_prompt, read_input_with_images},
+    config::{Config, Example, OutputMode},
+    extract::{self, parse_code_blocks, CodeBlock},
+    image::Image,
+    json,
+    markdown::MarkdownRenderer,
+    processor::{CompletionOptions, CompletionProcessor},
+    redact::Redactor,
+    system, Error, Result,
 };
-use clap::Parser;
-use dialoguer::console::style;
-use futures::StreamExt;
-use std::io::{stdout, Write};
+use atty::Stream;
+use clap::{Parser, ValueEnum};
+use dialoguer::{console::style, theme::ColorfulTheme, Select};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    fs,
+    io::{stdout, Write},
+    path::{Path, PathBuf},
+};
+use tokio::signal;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExtractMode {
+    /// Print the code of every block
+    Code,
+    /// Choose one block interactively
+    Pick,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AnswerFormat {
+    /// Free text, rendered as markdown on a terminal
+    Text,
+    /// A single JSON value, printed without anything around it
+    Json,
+}
+
+/// The result of `ask` with `--output json`.
+#[derive(Serialize)]
+struct AskOutput<'a> {
+    model: &'a str,
+    /// The answer, or only its code with `--extract`.
+    text: &'a str,
+    /// The parsed answer with `--format json`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json: Option<Value>,
+    usage: Usage,
+}
+
+/// How many times a JSON answer is requested before giving up.
+const MAX_JSON_ATTEMPTS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputStyle {
+    Plain,
+    Markdown,
+    Hidden,
+}
 
 #[derive(Parser)]
 pub struct AskArgs {
     #[command(flatten)]
     common: CommonArgs,
+    #[clap(
+        long = "image",
+        help = "Image to send to vision-capable models (can be repeated)",
+        number_of_values = 1
+    )]
+    images: Vec<PathBuf>,
+    #[clap(
+        long,
+        help = "Print the answer as plain text without rendering markdown",
+        default_value = "false"
+    )]
+    raw: bool,
+    #[clap(long, value_enum, help = "Output only the code blocks of the answer")]
+    extract: Option<ExtractMode>,
+    #[clap(
+        long,
+        help = "Write the code blocks of the answer to files in this directory"
+    )]
+    extract_to: Option<PathBuf>,
+    #[clap(
+        long,
+        value_enum,
+        help = "Format of the answer",
+        default_value = "text",
+        conflicts_with_all = ["extract", "extract_to"]
+    )]
+    format: AnswerFormat,
+    #[clap(
+        long,
+        help = "JSON schema file the answer must be valid against, implies --format json",
+        conflicts_with_all = ["extract", "extract_to"]
+    )]
+    json_schema: Option<PathBuf>,
 }
 
-pub async fn handle_ask(config: Config, args: AskArgs) -> Result<()> {
-    let processor = CompletionProcessor::new(GenAI::new());
-    let input = read_input(&args.common.input)?;
-    let prompt = parse_prompt(config, args.common.prompt, args.common.set, "")?;
-    let mut completion = processor
-        .generate_stream(
+pub async fn handle_ask(config: Config, args: AskArgs, output: OutputFormat) -> Result<()> {
+    let mut redactor = Redactor::new();
+    let (mut input, images) = read_input_with_images(
+        &args.common.prompt.input,
+        &args.common.prompt.files,
+        &args.images,
+    )?;
+    let (mut prompt, settings, examples) =
+        parse_prompt(config, &args.common, "", &mut input, &mut redactor)?;
+
+    let schema: Option<Value> = match &args.json_schema {
+        Some(path) => {
+            let schema = serde_json::from_str(&fs::read_to_string(path)?)?;
+            json::check_schema(&schema)?;
+            Some(schema)
+        }
+        None => None,
+    };
+    let json = schema.is_some() || args.format == AnswerFormat::Json;
+    if json {
+        prompt = format!("{}\n{}", prompt, json::instructions(schema.as_ref()));
+    }
+
+    // Flags on the command line win over the output mode saved with the prompt.
+    let extract = match (args.extract, settings.output) {
+        (Some(mode), _) => Some(mode),
+        (None, Some(OutputMode::Code)) if !args.raw && !json => Some(ExtractMode::Code),
+        _ => None,
+    };
+    let extracting = extract.is_some() || args.extract_to.is_some();
+    let output_style = if extracting || output == OutputFormat::Json {
+        OutputStyle::Hidden
+    } else if args.raw || settings.output == Some(OutputMode::Raw) || !atty::is(Stream::Stdout) {
+        OutputStyle::Plain
+    } else {
+        OutputStyle::Markdown
+    };
+
+    // The genai backend only handles text, so images go through OpenAI by default.
+    let default_backend = if images.is_empty() {
+        Backend::GenAI
+    } else {
+        Backend::OpenAI
+    };
+    let backend = args.common.backend(&settings, default_backend);
+    let mut options = args.common.completion_options(&settings);
+    // JSON mode makes the model answer with an object, which a schema for
+    // another type of value would reject every time.
+    options.json_object = json && json::expects_object(schema.as_ref());
+
+    // Images only go through the OpenAI API, where other providers' models
+    // would fail with a confusing error, so both are checked before sending.
+    if !images.is_empty() {
+        match backend {
+            Backend::GenAI => Err(Error::GenAIImageInput)?,
+            Backend::OpenAI if !is_openai_model(&options.model) => {
+                Err(Error::ImageInputNotSupported {
+                    model: options.model.clone(),
+                })?
+            }
+            _ => {}
+        }
+    }
+    let processor = CompletionProcessor::new(backend.generator()?);
+    let mut json_value = None;
+    let mut cancelled = false;
+    let whole_buf = if json {
+        let value = json_answer(
+            &processor,
+            &prompt,
+            &examples,
+            &input,
+            &images,
+            &options,
+            schema.as_ref(),
+        )
+        .await?;
+        let answer = serde_json::to_string_pretty(&value)?;
+        if output == OutputFormat::Text {
+            println!("{}", answer);
+        }
+        json_value = Some(value);
+        answer
+    } else {
+        let answer = stream_answer(
+            &processor,
             &prompt,
-            input.as_str(),
-            &args.common.model,
-            args.common.temperature,
+            &examples,
+            &input,
+            &images,
+            &options,
+            output_style,
         )
         .await?;
+        cancelled = answer.cancelled;
+        answer.text
+    };
+
+    // A cut off answer is only copied as is, it may not have all its code blocks.
+    if cancelled {
+        if args.common.copy {
+            system::copy_to_clipboard(&whole_buf)?;
+            output.message(format!("{} Copied to clipboard", style("✔").green()));
+        }
+        Err(Error::Interrupted)?
+    }
+
+    let mut copy_buf = whole_buf;
+    if extracting {
+        let blocks = parse_code_blocks(&copy_buf)?;
+        if blocks.is_empty() {
+            Err(Error::NoCodeBlocks)?
+        }
+
+        let selected: Vec<(usize, &CodeBlock)> = match extract {
+            Some(ExtractMode::Pick) => {
+                let index = pick_code_block(&blocks)?;
+                vec![(index, &blocks[index])]
+            }
+            _ => blocks.iter().enumerate().collect(),
+        };
+
+        if let Some(dir) = &args.extract_to {
+            write_code_blocks(dir, &selected)?;
+        }
+
+        copy_buf = selected
+            .iter()
+            .map(|(_, block)| block.code.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if extract.is_some() && output == OutputFormat::Text {
+            print!("{}", copy_buf);
+        }
+    }
+
+    if args.common.copy {
+        system::copy_to_clipboard(copy_buf.as_str())?;
+        output.message(format!("{} Copied to clipboard", style("✔").green()));
+    }
+
+    if output == OutputFormat::Json {
+        let result = AskOutput {
+            model: &options.model,
+            text: &copy_buf,
+            json: json_value,
+            usage: Usage {
+                estimated_tokens: estimate_tokens(&copy_buf),
+            },
+        };
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    }
+
+    Ok(())
+}
+
+fn pick_code_block(blocks: &[CodeBlock]) -> Result<usize> {
+    if blocks.len() == 1 {
+        return Ok(0);
+    }
+
+    let items: Vec<String> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| {
+            let first_line = block.code.lines().next().unwrap_or_default();
+            format!(
+                "{} [{}] {}",
+                block.file_path(i).display(),
+                block.lang.as_deref().unwrap_or("text"),
+                first_line
+            )
+        })
+        .collect();
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Pick a code block")
+        .items(&items)
+        .default(0)
+        .interact_on(&dialoguer::console::Term::stderr())?;
+
+    Ok(selection)
+}
+
+fn write_code_blocks(dir: &Path, blocks: &[(usize, &CodeBlock)]) -> Result<()> {
+    let paths = extract::target_paths(dir, blocks)?;
+    for ((_, block), path) in blocks.iter().zip(paths) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&path, &block.code)?;
+        eprintln!("{} Wrote {}", style("✔").green(), path.display());
+    }
+
+    Ok(())
+}
+
+/// The answer received from a stream, and whether it was cut short with Ctrl-C.
+struct StreamedAnswer {
+    text: String,
+    cancelled: bool,
+}
+
+/// Prints the answer as it arrives. The first Ctrl-C stops the request and
+/// keeps what was received, a second one quits right away.
+async fn stream_answer(
+    processor: &CompletionProcessor<Generator>,
+    prompt: &str,
+    examples: &[Example],
+    input: &str,
+    images: &[Image],
+    options: &CompletionOptions,
+    output: OutputStyle,
+) -> Result<StreamedAnswer> {
+    let completion = processor
+        .generate_stream(prompt, examples, input, images, options)
+        .await?;
+    print_stream(completion, output).await
+}
 
+/// Prints the chunks of an answer as they arrive, until the stream ends, fails
+/// or is cancelled with Ctrl-C.
+async fn print_stream(
+    mut completion: impl stream::Stream<Item = Result<String>> + Unpin,
+    output: OutputStyle,
+) -> Result<StreamedAnswer> {
     let mut whole_buf = String::new();
+    let mut renderer = if output == OutputStyle::Markdown {
+        Some(MarkdownRenderer::new()?)
+    } else {
+        None
+    };
 
     let mut lock = stdout().lock();
-    while let Some(content) = completion.next().await {
-        write!(lock, "{}", content)?;
+    let mut interrupted = None;
+    let mut cancelled = false;
+    let ctrl_c = signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    loop {
+        let content = tokio::select! {
+            content = completion.next() => match content {
+                Some(Ok(content)) => content,
+                Some(Err(e)) => {
+                    interrupted = Some(e);
+                    break;
+                }
+                None => break,
+            },
+            _ = &mut ctrl_c => {
+                cancelled = true;
+                break;
+            }
+        };
+
+        match renderer.as_mut() {
+            Some(renderer) => renderer.push(&content, &mut lock)?,
+            None if output == OutputStyle::Plain => write!(lock, "{}", content)?,
+            None => {}
+        }
         whole_buf.push_str(&content);
     }
 
-    writeln!(lock)?;
+    // Ctrl-C is handled from now on, so make a second one quit like it
+    // normally would, even while the partial answer is copied.
+    tokio::spawn(async {
+        if signal::ctrl_c().await.is_ok() {
+            std::process::exit(Error::Interrupted.exit_code());
+        }
+    });
 
-    if args.common.copy {
-        system::copy_to_clipboard(whole_buf.as_str())?;
-        println!("{} Copied to clipboard", style("✔").green());
+    match renderer.as_mut() {
+        Some(renderer) => renderer.finish(&mut lock)?,
+        None if output == OutputStyle::Plain => writeln!(lock)?,
+        None => {}
     }
 
-    Ok(())
+    // What was received is already printed, so say where the answer stops.
+    match interrupted {
+        Some(e) if whole_buf.is_empty() => Err(e),
+        Some(e) => Err(Error::StreamInterrupted {
+            tokens: estimate_tokens(&whole_buf),
+            source: Box::new(e),
+        }),
+        None => Ok(StreamedAnswer {
+            text: whole_buf,
+            cancelled,
+        }),
+    }
+}
+
+/// Asks for a JSON answer until one parses and is valid against the schema.
+/// A rejected answer is sent back as a previous turn along with what's wrong
+/// with it, so the model can correct it.
+async fn json_answer(
+    processor: &CompletionProcessor<Generator>,
+    prompt: &str,
+    examples: &[Example],
+    input: &str,
+    images: &[Image],
+    options: &CompletionOptions,
+    schema: Option<&Value>,
+) -> Result<Value> {
+    let mut examples = examples.to_vec();
+    let mut input = input.to_string();
+    let mut attempt = 1;
+    loop {
+        let answer = processor
+            .generate(prompt, &examples, &input, images, options)
+            .await?;
+
+        let problem = match json::parse_answer(&answer) {
+            Ok(value) => {
+                let errors = match schema {
+                    Some(schema) => json::validate(schema, &value)?,
+                    None => Vec::new(),
+                };
+                if errors.is_empty() {
+                    return Ok(value);
+                }
+                format!("is not valid against the schema: {}", errors.join("; "))
+            }
+            Err(e) => format!("is not valid JSON: {}", e),
+        };
+
+        if attempt == MAX_JSON_ATTEMPTS {
+            Err(Error::InvalidJsonAnswer {
+                attempts: attempt,
+                reason: format!("the answer {}", problem),
+            })?
+        }
+
+        eprintln!("{} The answer {}, retrying", style("!").yellow(), problem);
+        examples.push(Example {
+            input,
+            output: answer,
+        });
+        input = format!(
+            "Your answer {}. Reply again with only the corrected JSON.",
+            problem
+        );
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(items: Vec<Result<&str>>) -> impl stream::Stream<Item = Result<String>> + Unpin {
+        let items: Vec<Result<String>> = items
+            .into_iter()
+            .map(|item| item.map(str::to_string))
+            .collect();
+        stream::iter(items)
+    }
+
+    #[tokio::test]
+    async fn collects_the_whole_answer() {
+        let answer = print_stream(chunks(vec![Ok("Hello "), Ok("world")]), OutputStyle::Hidden)
+            .await
+            .unwrap();
+
+        assert_eq!(answer.text, "Hello world");
+        assert!(!answer.cancelled);
+    }
+
+    #[tokio::test]
+    async fn error_after_chunks_interrupts_the_answer() {
+        let completion = chunks(vec![
+            Ok("Hello "),
+            Ok("world, this is"),
+            Err(Error::EmptyResponse),
+            Ok("never read"),
+        ]);
+        let result = print_stream(completion, OutputStyle::Hidden).await;
+
+        match result {
+            Err(Error::StreamInterrupted { tokens, source }) => {
+                assert_eq!(tokens, estimate_tokens("Hello world, this is"));
+                assert!(matches!(*source, Error::EmptyResponse));
+            }
+            _ => panic!("expected an interrupted stream"),
+        }
+    }
+
+    #[tokio::test]
+    async fn error_before_any_chunk_is_returned_as_is() {
+        let result =
+            print_stream(chunks(vec![Err(Error::EmptyResponse)]), OutputStyle::Hidden).await;
+        assert!(matches!(result, Err(Error::EmptyResponse)));
+    }
 }
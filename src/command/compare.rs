@@ -0,0 +1,208 @@
+use super::{
+    estimate_tokens, read_input, resolve_prompt, AskFor, ErrorOutput, GenerationArgs, ModelArgs,
+    OutputFormat, PromptArgs, Usage,
+};
+use crate::backend::Backend;
+use crate::config::Config;
+use crate::processor::CompletionProcessor;
+use crate::Result;
+use clap::Parser;
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{ContentArrangement, Table};
+use dialoguer::console::style;
+use futures::future::join_all;
+use serde::Serialize;
+use std::{
+    fmt::Write,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+#[derive(Parser)]
+pub struct CompareArgs {
+    #[clap(
+        long,
+        required = true,
+        value_delimiter = ',',
+        help = "Models to compare, separated by commas (e.g. gpt-4o,claude-3-haiku-20240307)"
+    )]
+    models: Vec<String>,
+    #[clap(flatten)]
+    generation: GenerationArgs,
+    #[clap(flatten)]
+    prompt: PromptArgs,
+    #[clap(long, help = "Also save the comparison as a Markdown report")]
+    report: Option<PathBuf>,
+}
+
+/// The answer of one model, or why it failed.
+struct Run {
+    model: String,
+    latency: Duration,
+    answer: Result<String>,
+}
+
+/// The answer of one model with `--output json`.
+#[derive(Serialize)]
+struct RunOutput<'a> {
+    model: &'a str,
+    latency_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorOutput>,
+}
+
+impl<'a> From<&'a Run> for RunOutput<'a> {
+    fn from(run: &'a Run) -> Self {
+        let (text, usage, error) = match &run.answer {
+            Ok(answer) => (
+                Some(answer.as_str()),
+                Some(Usage {
+                    estimated_tokens: estimate_tokens(answer),
+                }),
+                None,
+            ),
+            Err(e) => (None, None, Some(e.into())),
+        };
+
+        RunOutput {
+            model: &run.model,
+            latency_secs: run.latency.as_secs_f64(),
+            text,
+            usage,
+            error,
+        }
+    }
+}
+
+pub async fn handle_compare(config: Config, args: CompareArgs, output: OutputFormat) -> Result<()> {
+    let input = read_input(&args.prompt.input, &args.prompt.files)?;
+    let (prompt, values) = resolve_prompt(
+        &config,
+        args.prompt.name.clone(),
+        args.prompt.set.clone(),
+        "",
+        &input,
+        AskFor::from_flag(args.prompt.interactive),
+    )?;
+    let rendered = prompt.template.render(&values)?;
+
+    // Every model gets the same rendered prompt, and they all run at once.
+    let runs = args.models.iter().map(|model| {
+        let model_args = ModelArgs {
+            model: Some(model.clone()),
+            generation: args.generation.clone(),
+        };
+        let backend = model_args.backend(&prompt.settings, Backend::GenAI);
+        let options = model_args.completion_options(&prompt.settings);
+        let (rendered, examples, input) = (&rendered, &prompt.examples, &input);
+
+        async move {
+            let start = Instant::now();
+            let answer = match backend.generator() {
+                Ok(generator) => {
+                    CompletionProcessor::new(generator)
+                        .generate(rendered, examples, input, &[], &options)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+
+            Run {
+                model: options.model,
+                latency: start.elapsed(),
+                answer,
+            }
+        }
+    });
+    let runs = join_all(runs).await;
+
+    match output {
+        OutputFormat::Text => {
+            let mut table = Table::new();
+            table
+                .set_header(runs.iter().map(|run| run.model.as_str()))
+                .load_preset(UTF8_FULL)
+                .apply_modifier(UTF8_ROUND_CORNERS)
+                .set_content_arrangement(ContentArrangement::Dynamic);
+            table.add_row(runs.iter().map(summary));
+            table.add_row(runs.iter().map(|run| match &run.answer {
+                Ok(answer) => answer.trim().to_string(),
+                Err(e) => format!("{} {}", style("✖").red(), e),
+            }));
+            println!("{}", table);
+        }
+        OutputFormat::Json => {
+            let runs: Vec<RunOutput> = runs.iter().map(RunOutput::from).collect();
+            println!("{}", serde_json::to_string_pretty(&runs)?);
+        }
+    }
+
+    if let Some(path) = &args.report {
+        fs::write(path, report(&rendered, &input, &runs))?;
+        eprintln!("{} Report saved to {}", style("✔").green(), path.display());
+    }
+
+    // Only fail when no model could answer, one failure is part of the comparison.
+    if runs.iter().all(|run| run.answer.is_err()) {
+        if let Some(Run { answer: Err(e), .. }) = runs.into_iter().next() {
+            Err(e)?
+        }
+    }
+
+    Ok(())
+}
+
+/// Latency and estimated size of an answer. The genai backend doesn't
+/// report token usage, so the count is always estimated from the answer.
+fn summary(run: &Run) -> String {
+    match &run.answer {
+        Ok(answer) => format!(
+            "{:.2}s · ~{} tokens (estimated)",
+            run.latency.as_secs_f64(),
+            estimate_tokens(answer)
+        ),
+        Err(_) => format!("{:.2}s · failed", run.latency.as_secs_f64()),
+    }
+}
+
+fn report(prompt: &str, input: &str, runs: &[Run]) -> String {
+    let mut report = String::from("# Model comparison\n\n");
+    if !prompt.trim().is_empty() {
+        let _ = writeln!(report, "## Prompt\n\n```\n{}\n```\n", prompt.trim());
+    }
+    let _ = writeln!(report, "## Input\n\n```\n{}\n```\n", input.trim());
+
+    report.push_str("## Summary\n\n| Model | Latency | Tokens (estimated) |\n|---|---|---|\n");
+    for run in runs {
+        let tokens = match &run.answer {
+            Ok(answer) => estimate_tokens(answer).to_string(),
+            Err(_) => "failed".to_string(),
+        };
+        let _ = writeln!(
+            report,
+            "| {} | {:.2}s | {} |",
+            run.model,
+            run.latency.as_secs_f64(),
+            tokens
+        );
+    }
+
+    for run in runs {
+        let _ = writeln!(report, "\n## {}\n", run.model);
+        match &run.answer {
+            Ok(answer) => report.push_str(answer.trim()),
+            Err(e) => {
+                let _ = write!(report, "Failed: {}", e);
+            }
+        }
+        report.push('\n');
+    }
+
+    report
+}
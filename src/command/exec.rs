@@ -1,13 +1,21 @@
-use super::{parse_prompt, read_input, CommonArgs};
+use super::{parse_prompt, read_input, CommonArgs, OutputFormat};
 use crate::{
-    backend::genai::GenAI,
+    backend::Backend,
     config::Config,
     processor::CompletionProcessor,
-    system::{self, copy_to_clipboard, run_cmd},
-    Result,
+    redact::Redactor,
+    risk::{self, Risk},
+    system::{copy_to_clipboard, exit_code, run_cmd_with_stdout},
+    Error, Result,
 };
+use atty::Stream;
 use clap::Parser;
 use dialoguer::{console::style, theme::ColorfulTheme, Editor, Input};
+use serde::Serialize;
+use std::{
+    io,
+    process::{ExitStatus, Stdio},
+};
 
 const RUN: &str = "r";
 const COPY: &str = "c";
@@ -22,6 +30,27 @@ Ensure the output is a valid shell command.
 If multiple steps are required, combine them using &&.
 Do not use Markdown formatting."#;
 
+/// What was done with the generated command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Run,
+    Copy,
+    Abort,
+    /// Nothing was asked, like with JSON output and no terminal.
+    None,
+}
+
+/// The result of `exec` with `--output json`.
+#[derive(Serialize)]
+struct ExecOutput<'a> {
+    model: &'a str,
+    command: &'a str,
+    risk: Risk,
+    action: Action,
+    exit_status: Option<i32>,
+}
+
 #[derive(Parser)]
 pub struct ExecArgs {
     #[command(flatten)]
@@ -35,66 +64,124 @@ pub struct ExecArgs {
     run: bool,
 }
 
-pub async fn handle_exec(config: Config, args: ExecArgs) -> Result<()> {
-    let processor = CompletionProcessor::new(GenAI::new());
-    let input = read_input(&args.common.input)?;
-    let default_prompt = SHELL_PROMPT
-        .replace("{shell}", &system::get_current_shell())
-        .replace("{os}", std::env::consts::OS);
-
-    let prompt = parse_prompt(config, args.common.prompt, args.common.set, &default_prompt)?;
-
-    let cmd = processor
-        .generate(
-            &prompt,
-            input.as_str(),
-            &args.common.model,
-            args.common.temperature,
-        )
+pub async fn handle_exec(config: Config, args: ExecArgs, output: OutputFormat) -> Result<()> {
+    let mut redactor = Redactor::new();
+    let mut input = read_input(&args.common.prompt.input, &args.common.prompt.files)?;
+    let (prompt, settings, examples) = parse_prompt(
+        config,
+        &args.common,
+        SHELL_PROMPT,
+        &mut input,
+        &mut redactor,
+    )?;
+
+    let backend = args.common.backend(&settings, Backend::GenAI);
+    let processor = CompletionProcessor::new(backend.generator()?);
+    let options = args.common.completion_options(&settings);
+    let generated = processor
+        .generate(&prompt, &examples, input.as_str(), &[], &options)
         .await?;
 
-    if args.run {
-        println!(
-            "Command to execute: {}",
-            dialoguer::console::style(&cmd).green()
-        );
-        run_cmd(&cmd)?;
+    // Put the real values back locally so the command is runnable.
+    let cmd = redactor.restore(&generated);
+
+    // `--run` runs the command straight away, then still asks what to do with it.
+    let ran = if args.run {
+        output.message(format!("Command to execute: {}", style(&cmd).green()));
+        Some(run_command(&cmd, output)?)
+    } else {
+        None
+    };
+
+    // Nothing is asked after a failed run, nor with JSON output and no
+    // terminal to ask on, the result just reports the command.
+    let failed = ran.is_some_and(|status| !status.success());
+    let (command, action, status) =
+        if failed || (output == OutputFormat::Json && !atty::is(Stream::Stderr)) {
+            let action = if ran.is_some() {
+                Action::Run
+            } else {
+                Action::None
+            };
+            (cmd, action, ran)
+        } else {
+            let (command, action) = prompt_action_for_cmd(cmd, output)?;
+            let status = match action {
+                Action::Run => Some(run_command(&command, output)?),
+                Action::Copy => {
+                    copy_to_clipboard(&command)?;
+                    output.message(format!("{} Copied to clipboard", style("✔").green()));
+                    None
+                }
+                Action::Abort | Action::None => None,
+            };
+            (command, action, status)
+        };
+
+    if output == OutputFormat::Json {
+        let result = ExecOutput {
+            model: &options.model,
+            command: &command,
+            risk: risk::assess(&command)?,
+            action,
+            exit_status: status.map(exit_code),
+        };
+        println!("{}", serde_json::to_string_pretty(&result)?);
     }
 
-    prompt_action_for_cmd(&cmd)
+    match (action, status) {
+        (Action::Abort, _) => Err(Error::Aborted),
+        (_, Some(status)) if !status.success() => Err(Error::CommandFailed {
+            command,
+            status: exit_code(status),
+        }),
+        _ => Ok(()),
+    }
 }
 
-fn prompt_action_for_cmd(command: &str) -> Result<()> {
-    println!(
-        "Command to execute: {}",
-        dialoguer::console::style(command).green()
-    );
-
-    let option = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("[R]un, [M]odify, [C]opy, [A]bort")
-        .validate_with(|input: &String| {
-            if OPTIONS.contains(&input.to_lowercase().as_str()) {
-                Ok(())
-            } else {
-                Err("Please enter 'r', 'm', 'c', 'a'")
-            }
-        })
-        .interact_text()?;
-
-    match option.to_lowercase().as_str() {
-        RUN => run_cmd(command),
-        MODIFY => {
-            if let Some(rv) = Editor::new().edit(command).unwrap() {
-                prompt_action_for_cmd(&rv)?;
-            }
-            Ok(())
-        }
-        COPY => {
-            copy_to_clipboard(command)?;
-            println!("{} Copied to clipboard", style("✔").green());
-            Ok(())
-        }
-        ABORT => Ok(()),
-        _ => unreachable!(), // This should never happen due to the validation
+/// Runs the command, keeping stdout for the JSON result with `--output json`
+/// by sending the command's output to stderr.
+fn run_command(command: &str, output: OutputFormat) -> Result<ExitStatus> {
+    match output {
+        OutputFormat::Text => run_cmd_with_stdout(command, Stdio::inherit()),
+        OutputFormat::Json => run_cmd_with_stdout(command, io::stderr()),
+    }
+}
+
+/// Asks what to do with the command, letting the user edit it first.
+fn prompt_action_for_cmd(mut command: String, output: OutputFormat) -> Result<(String, Action)> {
+    loop {
+        output.message(format!("Command to execute: {}", style(&command).green()));
+
+        let option = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("[R]un, [M]odify, [C]opy, [A]bort")
+            .validate_with(|input: &String| {
+                if OPTIONS.contains(&input.to_lowercase().as_str()) {
+                    Ok(())
+                } else {
+                    Err("Please enter 'r', 'm', 'c', 'a'")
+                }
+            })
+            .interact_text()?;
+
+        let action = match option.to_lowercase().as_str() {
+            RUN => Action::Run,
+            MODIFY => match Editor::new()
+                .edit(&command)
+                .map_err(|e| Error::EditorFailed {
+                    reason: e.to_string(),
+                })? {
+                Some(modified) => {
+                    command = modified;
+                    continue;
+                }
+                None => Action::Abort,
+            },
+            COPY => Action::Copy,
+            ABORT => Action::Abort,
+            _ => unreachable!(), // This should never happen due to the validation
+        };
+
+        return Ok((command, action));
     }
 }
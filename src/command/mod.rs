@@ -1,23 +1,37 @@
 mod ask;
+mod compare;
 mod exec;
+mod prompt_test;
 mod prompts;
 
 pub use self::ask::*;
+pub use self::compare::*;
 pub use self::exec::*;
 pub use self::prompts::*;
 
 use crate::Error;
 use crate::{
-    config::{Config, PromptValue},
+    backend::Backend,
+    builtins,
+    config::{Config, Example, PromptSettings, PromptValue},
+    image::Image,
+    processor::CompletionOptions,
+    redact::{Redactor, PLACEHOLDER_HINT},
+    template::{Template, VarType, Variable},
     Result,
 };
 use atty::Stream;
-use clap::Parser;
-use regex::Regex;
+use clap::{Parser, ValueEnum};
+use dialoguer::{console::style, theme::ColorfulTheme, Input, Select};
+use serde::Serialize;
 use std::result::Result as StdResult;
 use std::{
     collections::HashMap,
+    fmt::Display,
+    fs,
     io::{self, Read},
+    path::PathBuf,
+    sync::Once,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -30,49 +44,343 @@ impl From<KeyValue> for PromptValue {
     fn from(kv: KeyValue) -> Self {
         PromptValue {
             name: kv.key,
-            value: kv.value,
+            value: Some(kv.value),
         }
     }
 }
 
+/// How commands report their results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Text for people, with colors and tables
+    Text,
+    /// A JSON document on stdout, for scripts and other tools
+    Json,
+}
+
+impl OutputFormat {
+    /// Prints a message meant for people. It goes to stderr with JSON output
+    /// so stdout only carries the JSON document.
+    fn message(self, message: impl Display) {
+        match self {
+            OutputFormat::Text => println!("{}", message),
+            OutputFormat::Json => eprintln!("{}", message),
+        }
+    }
+}
+
+const DEFAULT_MODEL: &str = "gpt-4o";
+const DEFAULT_TEMPERATURE: f32 = 0.0;
+
+/// Renders the prompt selected by `args`, falling back to `default_prompt`,
+/// and returns it with the settings saved along with the prompt. With
+/// `--redact`, secrets and hosts in the input, the rendered prompt and the
+/// examples are replaced with placeholders that `redactor` can put back.
 fn parse_prompt(
     config: Config,
-    name: Option<String>,
-    values: Vec<KeyValue>,
+    args: &CommonArgs,
     default_prompt: &str,
-) -> Result<String> {
-    let name = if let Some(name) = name {
-        name
-    } else {
-        return Ok(default_prompt.to_string());
+    input: &mut String,
+    redactor: &mut Redactor,
+) -> Result<(String, PromptSettings, Vec<Example>)> {
+    if args.redact {
+        *input = redactor.redact(input)?;
+    }
+
+    let (prompt, values) = resolve_prompt(
+        &config,
+        args.prompt.name.clone(),
+        args.prompt.set.clone(),
+        default_prompt,
+        input,
+        AskFor::from_flag(args.prompt.interactive),
+    )?;
+    let mut rendered = prompt.template.render(&values)?;
+    let mut examples = prompt.examples;
+
+    if args.redact {
+        // Built-in values like {hostname} or {env.VAR} end up in the prompt.
+        rendered = format!("{}\n{}", redactor.redact(&rendered)?, PLACEHOLDER_HINT);
+        redactor.redact_examples(&mut examples)?;
+    }
+
+    Ok((rendered, prompt.settings, examples))
+}
+
+/// Which prompt variables to ask for on the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AskFor {
+    /// Never ask, like when running prompt tests.
+    Nothing,
+    /// Ask for required variables without a value, when there is a terminal.
+    Missing,
+    /// Ask for every variable not given with `--set`.
+    Everything,
+}
+
+impl AskFor {
+    fn from_flag(interactive: bool) -> Self {
+        if interactive {
+            AskFor::Everything
+        } else {
+            AskFor::Missing
+        }
+    }
+}
+
+/// A saved prompt with the prompts it extends and includes resolved.
+struct ComposedPrompt {
+    template: Template,
+    defaults: HashMap<String, String>,
+    settings: PromptSettings,
+    examples: Vec<Example>,
+}
+
+/// Loads a prompt and resolves its `extends` chain and `{> name}` includes.
+/// `chain` holds the prompts being resolved, so a cycle is reported instead
+/// of recursing forever.
+fn compose_prompt(config: &Config, name: &str, chain: &mut Vec<String>) -> Result<ComposedPrompt> {
+    let seen = chain.iter().any(|n| n == name);
+    chain.push(name.to_string());
+    if seen {
+        Err(Error::PromptCycle {
+            chain: chain.join(" -> "),
+        })?
+    }
+
+    let prompt = config.load_prompt(name)?.ok_or(Error::PromptNotFound {
+        name: name.to_string(),
+    })?;
+
+    // Defaults are merged so the closest ones win: those of the base prompt,
+    // then those of the included prompts, then the prompt's own.
+    let mut defaults = HashMap::new();
+    let mut settings = prompt.settings;
+    let mut examples = Vec::new();
+    let base = match &prompt.extends {
+        Some(base) => {
+            let base = compose_prompt(config, base, chain)?;
+            defaults.extend(base.defaults);
+            settings = settings.or(base.settings);
+            examples = base.examples;
+            Some(base.template)
+        }
+        None => None,
     };
 
-    let prompt = if let Some(prompt) = config.load_prompt(&name) {
+    let own = Template::parse(&prompt.content)?.expand_includes(&mut |partial| {
+        let partial = compose_prompt(config, partial, chain)?;
+        defaults.extend(partial.defaults);
+        Ok(partial.template)
+    })?;
+
+    let template = match base {
+        Some(mut template) => {
+            template.append(own);
+            template
+        }
+        None => own,
+    };
+    defaults.extend(
         prompt
-    } else {
-        Err(Error::PromptNotFound { name })?
+            .values
+            .into_iter()
+            .filter_map(|pv| pv.value.map(|value| (pv.name, value))),
+    );
+
+    examples.extend(prompt.examples);
+
+    chain.pop();
+    Ok(ComposedPrompt {
+        template,
+        defaults,
+        settings,
+        examples,
+    })
+}
+
+/// Loads the prompt template and works out the value of every variable it uses,
+/// validating them so rendering the template can't fail on a bad value.
+fn resolve_prompt(
+    config: &Config,
+    name: Option<String>,
+    values: Vec<KeyValue>,
+    default_prompt: &str,
+    input: &str,
+    ask_for: AskFor,
+) -> Result<(ComposedPrompt, HashMap<String, String>)> {
+    let mut prompt = match name {
+        Some(name) => compose_prompt(config, &name, &mut Vec::new())?,
+        None => ComposedPrompt {
+            template: Template::parse(default_prompt)?,
+            defaults: HashMap::new(),
+            settings: PromptSettings::default(),
+            examples: Vec::new(),
+        },
     };
 
-    // Merge the values from `prompt` and `values` into a single vector of `PromptValue`.
-    // If `prompt` and `values` contain values with the same name, the value from `values` is used.
-    let merged_values: Vec<PromptValue> = prompt
-        .values
+    let variables = prompt.template.variables();
+
+    // Reject `--set` keys the prompt doesn't use, they are almost always typos.
+    for kv in &values {
+        if !variables.iter().any(|v| v.name == kv.key) {
+            let suggestion = did_you_mean(&kv.key, variables.iter().map(|v| v.name.as_str()))
+                .map(|name| format!(", did you mean '{}'?", name))
+                .unwrap_or_default();
+            Err(Error::UnknownPromptValue {
+                name: kv.key.clone(),
+                suggestion,
+            })?
+        }
+    }
+
+    // Built-in variables referenced by the template, like `{os}` or `{env.HOME}`.
+    let builtin_values: Vec<(String, String)> = variables
+        .iter()
+        .filter_map(|v| builtins::resolve(&v.name, input).map(|value| (v.name.clone(), value)))
+        .collect();
+
+    let set_keys: Vec<String> = values.iter().map(|kv| kv.key.clone()).collect();
+
+    // Merge the defaults from the prompt, the built-in variables and `values` into a single map.
+    // If several contain a value with the same name, the last one wins, so `values` always does.
+    let mut merged_values: HashMap<String, String> = std::mem::take(&mut prompt.defaults)
         .into_iter()
-        .map(|pv| (pv.name, pv.value))
+        .chain(builtin_values)
         .chain(values.into_iter().map(|kv| (kv.key, kv.value)))
-        .collect::<HashMap<_, _>>()
-        .into_iter()
-        .map(|(name, value)| PromptValue { name, value })
         .collect();
 
-    let mut content = prompt.content;
+    // Ask for the values of the user variables that weren't given with `--set`: all of them
+    // in interactive mode, otherwise only the required ones when there is a terminal to ask on.
+    let can_ask = ask_for != AskFor::Nothing && atty::is(Stream::Stderr);
+    for variable in &variables {
+        if builtins::is_builtin(&variable.name) || set_keys.contains(&variable.name) {
+            continue;
+        }
+
+        let current = merged_values
+            .get(&variable.name)
+            .or(variable.default.as_ref())
+            .cloned();
+        if ask_for == AskFor::Everything || (current.is_none() && can_ask) {
+            let value = ask_prompt_value(variable, current)?;
+            merged_values.insert(variable.name.clone(), value);
+        }
+    }
+
+    // Check every variable before rendering so nothing is sent with a bad value.
+    for variable in &variables {
+        match merged_values.get(&variable.name) {
+            Some(value) if !variable.kind.accepts(value) => Err(Error::InvalidPromptValue {
+                name: variable.name.clone(),
+                value: value.clone(),
+                expected: variable.kind.describe(),
+            })?,
+            None if variable.default.is_none() => Err(Error::MissingPromptValue {
+                name: variable.name.clone(),
+            })?,
+            _ => {}
+        }
+    }
+
+    Ok((prompt, merged_values))
+}
+
+fn ask_prompt_value(variable: &Variable, current: Option<String>) -> Result<String> {
+    let theme = ColorfulTheme::default();
+    let options = match &variable.kind {
+        VarType::Enum(options) => options.clone(),
+        VarType::Bool => vec!["true".to_string(), "false".to_string()],
+        _ => Vec::new(),
+    };
+
+    if !options.is_empty() {
+        let default = current
+            .and_then(|value| options.iter().position(|option| *option == value))
+            .unwrap_or_default();
+        let selection = Select::with_theme(&theme)
+            .with_prompt(&variable.name)
+            .items(&options)
+            .default(default)
+            .interact()?;
+        return Ok(options[selection].clone());
+    }
+
+    let kind = variable.kind.clone();
+    let value = Input::<String>::with_theme(&theme)
+        .with_prompt(&variable.name)
+        .with_initial_text(current.unwrap_or_default())
+        .allow_empty(true)
+        .validate_with(move |value: &String| {
+            if kind.accepts(value) {
+                Ok(())
+            } else {
+                Err(format!("Expected {}", kind.describe()))
+            }
+        })
+        .interact_text()?;
+    Ok(value)
+}
+
+/// Returns the candidate closest to `name` when it's close enough to be a
+/// typo. Short names are always close to each other, so the distance must
+/// also be smaller than the name itself.
+fn did_you_mean<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| {
+            *distance <= 2.max(candidate.len() / 3) && *distance < name.chars().count()
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(row[j + 1])
+            };
+            prev = current;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Not every backend reports token usage, so it's estimated from the text.
+#[derive(Serialize)]
+struct Usage {
+    estimated_tokens: usize,
+}
 
-    for kv in merged_values {
-        let re = Regex::new(&format!("\\{{{}(:\\w+)?\\}}", kv.name))?;
-        content = re.replace_all(&content, kv.value.as_str()).to_string();
+/// An error as reported with `--output json`.
+#[derive(Serialize)]
+struct ErrorOutput {
+    code: &'static str,
+    message: String,
+}
+
+impl From<&Error> for ErrorOutput {
+    fn from(error: &Error) -> Self {
+        ErrorOutput {
+            code: error.code(),
+            message: error.to_string(),
+        }
     }
+}
 
-    Ok(content)
+/// Rough token count using the common approximation of four characters per token.
+fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
 }
 
 fn parse_key_val(s: &str) -> StdResult<KeyValue, String> {
@@ -85,22 +393,84 @@ fn parse_key_val(s: &str) -> StdResult<KeyValue, String> {
     })
 }
 
-#[derive(Parser, Clone)]
-pub struct CommonArgs {
+/// Options to pick the model, shared by every command that calls one.
+#[derive(Debug, Parser, Clone)]
+pub struct ModelArgs {
     #[clap(
         short,
         long,
-        help = "Model name (e.g. gpt-4o)",
-        default_value = "gpt-4o"
+        help = "Model name (e.g. gpt-4o) [default: the prompt's model or gpt-4o]"
     )]
-    model: String,
+    model: Option<String>,
+    #[clap(flatten)]
+    generation: GenerationArgs,
+}
+
+/// Options for how the model answers, shared with `compare`, which picks
+/// several models instead of one.
+#[derive(Debug, Parser, Clone)]
+pub struct GenerationArgs {
     #[clap(
         short,
         long,
-        help = "Temperature value to set the randomness of the output",
-        default_value = "0.0"
+        help = "Temperature value to set the randomness of the output, ignored by the genai backend [default: the prompt's temperature or 0.0]"
+    )]
+    temperature: Option<f32>,
+    #[clap(long, value_enum, help = "Backend used to call the model")]
+    backend: Option<Backend>,
+    #[clap(
+        long,
+        help = "Maximum number of tokens to generate, ignored by the genai backend"
     )]
-    temperature: f32,
+    max_tokens: Option<u32>,
+}
+
+impl ModelArgs {
+    /// Completion options from the command line, falling back to the prompt's settings.
+    fn completion_options(&self, settings: &PromptSettings) -> CompletionOptions {
+        CompletionOptions {
+            model: self
+                .model
+                .clone()
+                .or_else(|| settings.model.clone())
+                .unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            temperature: self
+                .generation
+                .temperature
+                .or(settings.temperature)
+                .unwrap_or(DEFAULT_TEMPERATURE),
+            max_tokens: self.generation.max_tokens.or(settings.max_tokens),
+            json_object: false,
+        }
+    }
+
+    /// The backend from the command line or the prompt's settings. genai
+    /// doesn't pass the temperature and max tokens on to the model, so setting
+    /// either with it prints a warning, once.
+    fn backend(&self, settings: &PromptSettings, default: Backend) -> Backend {
+        let generation = &self.generation;
+        let backend = generation.backend.or(settings.backend).unwrap_or(default);
+        let tuned = generation.temperature.or(settings.temperature).is_some()
+            || generation.max_tokens.or(settings.max_tokens).is_some();
+
+        if backend == Backend::GenAI && tuned {
+            static WARNING: Once = Once::new();
+            WARNING.call_once(|| {
+                eprintln!(
+                    "{} The genai backend ignores the temperature and max tokens, use --backend openai to apply them to OpenAI models",
+                    style("!").yellow()
+                )
+            });
+        }
+
+        backend
+    }
+}
+
+#[derive(Parser, Clone)]
+pub struct CommonArgs {
+    #[clap(flatten)]
+    model: ModelArgs,
     #[clap(
         short,
         long,
@@ -108,29 +478,314 @@ pub struct CommonArgs {
         default_value = "false"
     )]
     copy: bool,
-    #[clap(long, help = "Prompt to use for the completion")]
-    prompt: Option<String>,
+    #[clap(
+        long,
+        help = "Mask secrets and hosts in the input with placeholders before sending it",
+        default_value = "false"
+    )]
+    redact: bool,
+    #[clap(flatten)]
+    prompt: PromptArgs,
+}
+
+/// The prompt to use and what to fill it with, shared by every command that
+/// renders one.
+#[derive(Parser, Clone)]
+pub struct PromptArgs {
+    #[clap(
+        id = "prompt",
+        long = "prompt",
+        value_name = "PROMPT",
+        help = "Prompt to use for the completion"
+    )]
+    name: Option<String>,
     #[arg(short, long, value_parser = parse_key_val, number_of_values = 1)]
     set: Vec<KeyValue>,
+    #[arg(
+        short,
+        long = "file",
+        help = "File or glob to attach as context (can be repeated)",
+        number_of_values = 1
+    )]
+    files: Vec<String>,
+    #[clap(
+        short,
+        long,
+        help = "Ask for the value of every prompt variable not given with --set",
+        default_value = "false"
+    )]
+    interactive: bool,
     #[arg(required = true)]
     input: String,
 }
 
-pub fn read_input(input: &str) -> Result<String> {
-    let mut buffer = String::new();
+impl CommonArgs {
+    fn completion_options(&self, settings: &PromptSettings) -> CompletionOptions {
+        self.model.completion_options(settings)
+    }
 
-    if !atty::is(Stream::Stdin) {
-        let mut stdin = io::stdin();
-        let mut stdin_buffer = String::new();
+    fn backend(&self, settings: &PromptSettings, default: Backend) -> Backend {
+        self.model.backend(settings, default)
+    }
+}
 
-        // Read from stdin asynchronously
-        stdin.read_to_string(&mut stdin_buffer)?;
+pub fn read_input(input: &str, files: &[String]) -> Result<String> {
+    let stdin = String::from_utf8(read_stdin()?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    build_input(&stdin, input, files)
+}
 
-        if !stdin_buffer.trim().is_empty() {
-            buffer.push_str(&stdin_buffer);
+/// Like `read_input`, but also loads the given images and treats image data
+/// piped through stdin as an image instead of text.
+pub fn read_input_with_images(
+    input: &str,
+    files: &[String],
+    image_paths: &[PathBuf],
+) -> Result<(String, Vec<Image>)> {
+    let mut images = image_paths
+        .iter()
+        .map(|path| Image::load(path))
+        .collect::<Result<Vec<_>>>()?;
+
+    let stdin = read_stdin()?;
+    let text = match Image::from_bytes(&stdin) {
+        Some(image) => {
+            images.push(image);
+            String::new()
+        }
+        None => {
+            String::from_utf8(stdin).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
         }
+    };
+
+    Ok((build_input(&text, input, files)?, images))
+}
+
+fn read_stdin() -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    if !atty::is(Stream::Stdin) {
+        io::stdin().read_to_end(&mut buffer)?;
+    }
+
+    Ok(buffer)
+}
+
+fn build_input(stdin: &str, input: &str, files: &[String]) -> Result<String> {
+    let mut buffer = read_files(files)?;
+    if !stdin.trim().is_empty() {
+        buffer.push_str(stdin);
     }
 
     buffer.push_str(input);
     Ok(buffer)
 }
+
+const MAX_FILE_SIZE: u64 = 256 * 1024;
+
+fn read_files(patterns: &[String]) -> Result<String> {
+    let mut buffer = String::new();
+
+    for pattern in patterns {
+        let mut matched = false;
+        for entry in glob::glob(pattern)? {
+            let path = entry.map_err(io::Error::from)?;
+            if !path.is_file() {
+                continue;
+            }
+
+            matched = true;
+            let display = path.display().to_string();
+            let size = fs::metadata(&path)?.len();
+            if size > MAX_FILE_SIZE {
+                Err(Error::FileTooLarge {
+                    path: display.clone(),
+                    limit: MAX_FILE_SIZE,
+                })?
+            }
+
+            // Skip anything that doesn't look like text instead of sending garbage.
+            let bytes = fs::read(&path)?;
+            let content = match String::from_utf8(bytes) {
+                Ok(content) if !content.contains('\0') => content,
+                _ => {
+                    eprintln!("{} Skipping binary file {}", style("!").yellow(), display);
+                    continue;
+                }
+            };
+
+            buffer.push_str(&format!("File: {}\n```\n{}", display, content));
+            if !content.ends_with('\n') {
+                buffer.push('\n');
+            }
+            buffer.push_str("```\n\n");
+        }
+
+        if !matched {
+            Err(Error::FileNotFound {
+                pattern: pattern.clone(),
+            })?
+        }
+    }
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Prompt;
+
+    fn set(values: &[(&str, &str)]) -> Vec<KeyValue> {
+        values
+            .iter()
+            .map(|(key, value)| KeyValue {
+                key: key.to_string(),
+                value: value.to_string(),
+            })
+            .collect()
+    }
+
+    /// Resolves `template` as the default prompt, without asking for anything.
+    fn resolve(template: &str, values: &[(&str, &str)]) -> Result<HashMap<String, String>> {
+        let config = Config::for_test("resolve");
+        resolve_prompt(&config, None, set(values), template, "", AskFor::Nothing)
+            .map(|(_, values)| values)
+    }
+
+    #[test]
+    fn fills_in_defaults_and_set_values() {
+        let values = resolve(
+            "{text} to {to:spanish} {n:int=2}",
+            &[("text", "hi"), ("n", "3")],
+        );
+        let values = values.unwrap();
+
+        // Template defaults are left to rendering.
+        assert_eq!(values["text"], "hi");
+        assert_eq!(values["n"], "3");
+        assert!(!values.contains_key("to"));
+    }
+
+    #[test]
+    fn unknown_key_suggests_the_closest_variable() {
+        let result = resolve("Translate to {language}", &[("langauge", "es")]);
+        assert!(matches!(
+            result,
+            Err(Error::UnknownPromptValue { ref name, ref suggestion })
+                if name == "langauge" && suggestion == ", did you mean 'language'?"
+        ));
+    }
+
+    #[test]
+    fn short_unknown_key_suggests_nothing() {
+        let result = resolve("Translate to {to}", &[("xx", "1")]);
+        assert!(matches!(
+            result,
+            Err(Error::UnknownPromptValue { ref suggestion, .. }) if suggestion.is_empty()
+        ));
+    }
+
+    #[test]
+    fn value_of_the_wrong_type_is_an_error() {
+        let result = resolve("{count:int} {lang:enum(en|es)=en}", &[("count", "many")]);
+        assert!(matches!(
+            result,
+            Err(Error::InvalidPromptValue { ref name, ref expected, .. })
+                if name == "count" && expected == "an integer"
+        ));
+
+        let result = resolve(
+            "{count:int} {lang:enum(en|es)=en}",
+            &[("count", "1"), ("lang", "fr")],
+        );
+        assert!(matches!(
+            result,
+            Err(Error::InvalidPromptValue { ref expected, .. }) if expected == "one of en, es"
+        ));
+    }
+
+    #[test]
+    fn missing_required_value_is_an_error() {
+        let result = resolve("Hello {name}, {#if formal}sir{/if}", &[]);
+        assert!(matches!(
+            result,
+            Err(Error::MissingPromptValue { ref name }) if name == "name"
+        ));
+    }
+
+    fn save(config: &Config, name: &str, document: &str) {
+        config
+            .save_prompt(&Prompt::from_markdown(name, document).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn closest_defaults_win() {
+        let config = Config::for_test("compose-defaults");
+        save(
+            &config,
+            "base",
+            "---\nvariables:\n  a: base\n  b: base\n  c: base\n---\nBase {a}",
+        );
+        save(
+            &config,
+            "partial",
+            "---\nvariables:\n  b: partial\n  c: partial\n---\nPartial {b}",
+        );
+        save(
+            &config,
+            "child",
+            "---\nextends: base\nvariables:\n  c: child\n---\nChild {> partial} {c}",
+        );
+
+        let prompt = compose_prompt(&config, "child", &mut Vec::new()).unwrap();
+        assert_eq!(prompt.defaults["a"], "base");
+        assert_eq!(prompt.defaults["b"], "partial");
+        assert_eq!(prompt.defaults["c"], "child");
+        let variables: Vec<_> = prompt
+            .template
+            .variables()
+            .into_iter()
+            .map(|v| v.name)
+            .collect();
+        assert_eq!(variables, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn extends_cycle_is_an_error() {
+        let config = Config::for_test("compose-extends");
+        save(&config, "a", "---\nextends: b\n---\nA");
+        save(&config, "b", "---\nextends: a\n---\nB");
+
+        assert!(matches!(
+            compose_prompt(&config, "a", &mut Vec::new()),
+            Err(Error::PromptCycle { ref chain }) if chain == "a -> b -> a"
+        ));
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let config = Config::for_test("compose-includes");
+        save(&config, "a", "A {> b}");
+        save(&config, "b", "B {#if x}{> a}{/if}");
+        save(&config, "c", "C {> c}");
+
+        assert!(matches!(
+            compose_prompt(&config, "a", &mut Vec::new()),
+            Err(Error::PromptCycle { ref chain }) if chain == "a -> b -> a"
+        ));
+        assert!(matches!(
+            compose_prompt(&config, "c", &mut Vec::new()),
+            Err(Error::PromptCycle { ref chain }) if chain == "c -> c"
+        ));
+    }
+
+    #[test]
+    fn did_you_mean_needs_a_close_enough_name() {
+        let names = ["language", "to", "tone"];
+        assert_eq!(did_you_mean("languge", names.into_iter()), Some("language"));
+        assert_eq!(did_you_mean("tonee", names.into_iter()), Some("tone"));
+        assert_eq!(did_you_mean("xx", names.into_iter()), None);
+        assert_eq!(did_you_mean("format", names.into_iter()), None);
+    }
+}
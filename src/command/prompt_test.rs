@@ -0,0 +1,374 @@
+use super::{resolve_prompt, AskFor, KeyValue, ModelArgs, OutputFormat};
+use crate::backend::{mock::Mock, Backend, Generator};
+use crate::config::{Config, Scalar};
+use crate::processor::CompletionProcessor;
+use crate::system::run_cmd_with_input;
+use crate::{json, Error, Result};
+use clap::Parser;
+use dialoguer::console::style;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Parser)]
+pub struct TestArgs {
+    #[clap(name = "Name of the prompt")]
+    name: String,
+    #[clap(
+        long,
+        help = "Test file to run [default: <name>.tests.yaml next to the prompt]"
+    )]
+    file: Option<PathBuf>,
+    #[clap(flatten)]
+    model: ModelArgs,
+}
+
+/// Test cases for a prompt, usually kept next to it as `<name>.tests.yaml`.
+#[derive(Deserialize, Debug)]
+struct TestFile {
+    cases: Vec<TestCase>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TestCase {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    input: String,
+    /// Values for the prompt variables, like `--set`.
+    #[serde(default)]
+    set: BTreeMap<String, Scalar>,
+    /// Answer of the mock backend, so assertions can be checked without a model.
+    #[serde(default)]
+    mock_output: Option<String>,
+    /// Written as `- contains: text` rather than with YAML tags.
+    #[serde(
+        default,
+        rename = "assert",
+        deserialize_with = "serde_yaml::with::singleton_map_recursive::deserialize"
+    )]
+    assertions: Vec<Assertion>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Assertion {
+    /// The answer contains the text.
+    Contains(String),
+    /// The answer matches the regular expression.
+    Regex(String),
+    /// The answer is exactly the text, ignoring surrounding whitespace.
+    Exact(String),
+    /// The answer has at most this many characters.
+    MaxLength(usize),
+    /// The answer is JSON valid against the schema, given inline or as a
+    /// path relative to the test file.
+    JsonSchema(Value),
+    /// The command exits successfully with the answer on its stdin.
+    Command(String),
+}
+
+/// The result of one case with `--output json`.
+#[derive(Serialize, Debug)]
+struct CaseOutput {
+    name: String,
+    passed: bool,
+    /// Why the case failed, empty when it passed.
+    failures: Vec<String>,
+    /// The answer, missing when the model couldn't give one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+}
+
+pub async fn handle_test(config: &Config, args: TestArgs, output: OutputFormat) -> Result<()> {
+    let path = match args.file {
+        Some(path) => path,
+        None => match config.find_prompt(&args.name) {
+            Some(prompt_path) => prompt_path.with_file_name(format!("{}.tests.yaml", args.name)),
+            None => Err(Error::PromptNotFound {
+                name: args.name.clone(),
+            })?,
+        },
+    };
+    if !path.is_file() {
+        Err(Error::NoPromptTests {
+            path: path.display().to_string(),
+        })?
+    }
+
+    let test_file: TestFile =
+        serde_yaml::from_str(&fs::read_to_string(&path)?).map_err(|e| Error::InvalidTestFile {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        })?;
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+
+    let total = test_file.cases.len();
+    let mut failed = 0;
+    let mut results = Vec::new();
+    for (i, case) in test_file.cases.into_iter().enumerate() {
+        let name = case
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("case {}", i + 1));
+
+        let (answer, failures) = match run_case(config, &args.name, &args.model, &case).await {
+            Ok(answer) => {
+                let failures = case
+                    .assertions
+                    .iter()
+                    .map(|assertion| check(assertion, &answer, base_dir))
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                (Some(answer), failures)
+            }
+            Err(e) => (None, vec![e.to_string()]),
+        };
+
+        if !failures.is_empty() {
+            failed += 1;
+        }
+        match output {
+            OutputFormat::Text => print_case(&name, &failures, answer.as_deref()),
+            OutputFormat::Json => results.push(CaseOutput {
+                name,
+                passed: failures.is_empty(),
+                failures,
+                output: answer,
+            }),
+        }
+    }
+
+    match output {
+        OutputFormat::Text => println!("\n{} passed, {} failed", total - failed, failed),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&results)?),
+    }
+    if failed > 0 {
+        Err(Error::PromptTestsFailed { failed, total })?
+    }
+
+    Ok(())
+}
+
+fn print_case(name: &str, failures: &[String], answer: Option<&str>) {
+    if failures.is_empty() {
+        println!("{} {}", style("✔").green(), name);
+        return;
+    }
+
+    println!("{} {}", style("✖").red(), name);
+    for failure in failures {
+        println!("    - {}", failure);
+    }
+    if let Some(answer) = answer {
+        for line in answer.lines() {
+            println!("    {}", style(format!("│ {}", line)).dim());
+        }
+    }
+}
+
+async fn run_case(
+    config: &Config,
+    name: &str,
+    model: &ModelArgs,
+    case: &TestCase,
+) -> Result<String> {
+    let values = case
+        .set
+        .iter()
+        .map(|(key, value)| KeyValue {
+            key: key.clone(),
+            value: value.to_string(),
+        })
+        .collect();
+    let (prompt, values) = resolve_prompt(
+        config,
+        Some(name.to_string()),
+        values,
+        "",
+        &case.input,
+        AskFor::Nothing,
+    )?;
+    let rendered = prompt.template.render(&values)?;
+
+    let generator = match model.backend(&prompt.settings, Backend::GenAI) {
+        Backend::Mock => Generator::Mock(Mock::new(case.mock_output.clone())),
+        backend => backend.generator()?,
+    };
+    CompletionProcessor::new(generator)
+        .generate(
+            &rendered,
+            &prompt.examples,
+            &case.input,
+            &[],
+            &model.completion_options(&prompt.settings),
+        )
+        .await
+}
+
+/// Checks an assertion against the answer, returning why it failed.
+fn check(assertion: &Assertion, output: &str, base_dir: &Path) -> Result<Option<String>> {
+    let failure = match assertion {
+        Assertion::Contains(text) => {
+            (!output.contains(text.as_str())).then(|| format!("doesn't contain '{}'", text))
+        }
+        Assertion::Regex(pattern) => {
+            (!Regex::new(pattern)?.is_match(output)).then(|| format!("doesn't match /{}/", pattern))
+        }
+        Assertion::Exact(text) => {
+            (output.trim() != text.trim()).then(|| format!("isn't exactly '{}'", text))
+        }
+        Assertion::MaxLength(max) => {
+            let len = output.trim().chars().count();
+            (len > *max).then(|| format!("is {} characters long, more than {}", len, max))
+        }
+        Assertion::JsonSchema(schema) => {
+            let schema = match schema {
+                Value::String(path) => {
+                    serde_json::from_str(&fs::read_to_string(base_dir.join(path))?)?
+                }
+                schema => schema.clone(),
+            };
+            match json::parse_answer(output) {
+                Ok(value) => {
+                    let errors = json::validate(&schema, &value)?;
+                    (!errors.is_empty())
+                        .then(|| format!("doesn't match the schema: {}", errors.join("; ")))
+                }
+                Err(e) => Some(format!("isn't valid JSON: {}", e)),
+            }
+        }
+        Assertion::Command(command) => {
+            (!run_cmd_with_input(command, output)?).then(|| format!("`{}` failed", command))
+        }
+    };
+
+    Ok(failure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// Parses assertions written like in a test file.
+    fn assertion(yaml: &str) -> Assertion {
+        serde_yaml::with::singleton_map_recursive::deserialize(serde_yaml::Deserializer::from_str(
+            yaml,
+        ))
+        .unwrap()
+    }
+
+    fn passes(yaml: &str, output: &str) -> bool {
+        check(&assertion(yaml), output, Path::new("."))
+            .unwrap()
+            .is_none()
+    }
+
+    #[test]
+    fn parses_the_readme_example() {
+        let test_file: TestFile = serde_yaml::from_str(
+            r#"
+cases:
+  - name: documentation change
+    input: fixed a typo in the install section
+    mock_output: "docs: fix typo in the installation guide"
+    assert:
+      - regex: '^(feat|fix|docs|chore)(\(.+\))?: '
+      - max_length: 72
+  - name: release notes as JSON
+    input: release 1.2.0
+    set:
+      format: json
+    assert:
+      - json_schema: release.schema.json
+      - command: jq -e '.version == "1.2.0"'
+"#,
+        )
+        .unwrap();
+
+        let [docs, release] = &test_file.cases[..] else {
+            panic!("expected two cases, got {:?}", test_file.cases);
+        };
+        assert_eq!(docs.name.as_deref(), Some("documentation change"));
+        assert!(docs.mock_output.is_some());
+        assert!(matches!(
+            docs.assertions[..],
+            [Assertion::Regex(_), Assertion::MaxLength(72)]
+        ));
+        assert_eq!(release.set["format"].to_string(), "json");
+        assert!(matches!(
+            &release.assertions[..],
+            [Assertion::JsonSchema(Value::String(path)), Assertion::Command(_)]
+                if path == "release.schema.json"
+        ));
+    }
+
+    #[test]
+    fn contains() {
+        assert!(passes("contains: fix", "docs: fix typo"));
+        assert!(!passes("contains: feat", "docs: fix typo"));
+    }
+
+    #[test]
+    fn regex() {
+        assert!(passes(
+            "regex: '^docs(\\(.+\\))?: '",
+            "docs(readme): fix typo"
+        ));
+        assert!(!passes("regex: '^feat: '", "docs: fix typo"));
+        assert!(check(&assertion("regex: '('"), "", Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn exact_ignores_surrounding_whitespace() {
+        assert!(passes("exact: ' yes'", "yes\n"));
+        assert!(!passes("exact: yes", "yes, sure"));
+    }
+
+    #[test]
+    fn max_length_counts_characters() {
+        assert!(passes("max_length: 5", "héllo\n"));
+        assert!(!passes("max_length: 4", "héllo"));
+    }
+
+    #[test]
+    fn inline_json_schema() {
+        let schema = "json_schema: {type: object, required: [version]}";
+        assert!(passes(schema, r#"{"version": "1.2.0"}"#));
+        assert!(passes(schema, "```json\n{\"version\": \"1.2.0\"}\n```"));
+        assert!(!passes(schema, r#"{"name": "x"}"#));
+        assert!(!passes(schema, "not json"));
+    }
+
+    #[test]
+    fn json_schema_path_is_relative_to_the_test_file() {
+        let dir = env::temp_dir().join(format!("shelldon-prompt-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("release.schema.json"),
+            r#"{"type": "object", "required": ["version"]}"#,
+        )
+        .unwrap();
+
+        let schema = assertion("json_schema: release.schema.json");
+        assert!(check(&schema, r#"{"version": "1"}"#, &dir)
+            .unwrap()
+            .is_none());
+        assert!(check(&schema, "{}", &dir).unwrap().is_some());
+        assert!(check(&schema, "{}", Path::new("/nonexistent")).is_err());
+    }
+
+    #[test]
+    fn command_gets_the_answer_on_stdin() {
+        assert!(passes("command: grep -q 1.2.0", "release 1.2.0"));
+        assert!(!passes("command: grep -q 1.3.0", "release 1.2.0"));
+    }
+}
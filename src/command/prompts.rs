@@ -1,10 +1,20 @@
-use crate::config::Config;
-use crate::{Error, Result};
-use clap::Parser;
+use super::prompt_test::{handle_test, TestArgs};
+use super::{estimate_tokens, parse_key_val, resolve_prompt, AskFor, KeyValue, OutputFormat};
+use crate::backend::Backend;
+use crate::bundle::{self, Bundle};
+use crate::config::{parse_prompt, Config, OutputMode, Prompt, PromptSettings};
+use crate::{template, Error, Result};
+use clap::{Parser, ValueEnum};
 use comfy_table::modifiers::UTF8_ROUND_CORNERS;
 use comfy_table::presets::UTF8_FULL;
 use comfy_table::{ContentArrangement, Table};
 use dialoguer::{console::style, Editor};
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+use std::{collections::HashMap, fs, path::PathBuf};
+
+/// Name of the current version of a prompt in history commands.
+const CURRENT: &str = "current";
 
 #[derive(Debug, Parser)]
 pub struct PromptsArgs {
@@ -18,6 +28,120 @@ pub struct NameArgs {
     name: String,
 }
 
+#[derive(Debug, Parser)]
+pub struct ListArgs {
+    #[clap(
+        long,
+        help = "Show where each prompt comes from, including the ones that are shadowed"
+    )]
+    source: bool,
+}
+
+/// Where a prompt comes from, as listed by `prompts list --source`.
+#[derive(Serialize)]
+struct PromptSource {
+    name: String,
+    source: String,
+    path: String,
+    status: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct DiffArgs {
+    #[clap(name = "Name of the prompt")]
+    name: String,
+    #[clap(help = "Version to compare from, as shown by history")]
+    from: String,
+    #[clap(
+        default_value = CURRENT,
+        help = "Version to compare to, the current prompt by default"
+    )]
+    to: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct RollbackArgs {
+    #[clap(name = "Name of the prompt")]
+    name: String,
+    #[clap(help = "Version to restore, as shown by history")]
+    version: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct ExportArgs {
+    #[clap(help = "Prompts to export, all of them when none is given")]
+    names: Vec<String>,
+    #[clap(
+        short,
+        long = "out",
+        help = "Bundle file to write, stdout when not given"
+    )]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Conflict {
+    /// Keep the existing prompt
+    Skip,
+    /// Replace the existing prompt
+    Overwrite,
+    /// Import the prompt with a new name like `name-2`
+    Rename,
+}
+
+#[derive(Debug, Parser)]
+pub struct ImportArgs {
+    #[clap(help = "Bundle, prompt file or directory of prompt files to import")]
+    source: String,
+    #[clap(
+        long,
+        value_enum,
+        default_value = "skip",
+        help = "What to do with prompts that already exist"
+    )]
+    on_conflict: Conflict,
+    #[clap(long, help = "Only show what would be imported")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct RenderArgs {
+    #[clap(name = "Name of the prompt")]
+    name: String,
+    #[arg(short, long, value_parser = parse_key_val, number_of_values = 1)]
+    set: Vec<KeyValue>,
+    #[clap(
+        short,
+        long,
+        help = "Ask for the value of every prompt variable not given with --set",
+        default_value = "false"
+    )]
+    interactive: bool,
+    #[clap(
+        long,
+        help = "Input used to resolve built-in variables like {input_lines}"
+    )]
+    input: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct SettingsArgs {
+    #[clap(name = "Name of the prompt")]
+    name: String,
+    #[clap(short, long, help = "Preferred model (e.g. gpt-4o)")]
+    model: Option<String>,
+    #[clap(short, long, help = "Preferred temperature")]
+    temperature: Option<f32>,
+    #[clap(long, value_enum, help = "Preferred backend")]
+    backend: Option<Backend>,
+    #[clap(long, help = "Maximum number of tokens to generate")]
+    max_tokens: Option<u32>,
+    #[clap(long = "output-mode", value_enum, help = "Output mode for ask")]
+    output: Option<OutputMode>,
+    #[clap(long, help = "Remove all the settings before applying the new ones")]
+    reset: bool,
+}
+
 #[derive(Debug, Parser)]
 pub enum Command {
     #[clap(about = "Create a new prompt")]
@@ -25,42 +149,83 @@ pub enum Command {
     #[clap(about = "Edit an existing prompt")]
     Edit(NameArgs),
     #[clap(about = "List all prompts")]
-    List,
+    List(ListArgs),
     #[clap(about = "Delete an existing prompt")]
     Delete(NameArgs),
+    #[clap(about = "Show a prompt as it would be sent, without calling any model")]
+    Render(RenderArgs),
+    #[clap(about = "Show or change the model settings of a prompt")]
+    Settings(SettingsArgs),
+    #[clap(about = "Run the test cases of a prompt and check its answers")]
+    Test(TestArgs),
+    #[clap(about = "List the previous versions of a prompt")]
+    History(NameArgs),
+    #[clap(about = "Show the changes between two versions of a prompt")]
+    Diff(DiffArgs),
+    #[clap(about = "Restore a previous version of a prompt")]
+    Rollback(RollbackArgs),
+    #[clap(about = "Export prompts to a bundle file")]
+    Export(ExportArgs),
+    #[clap(about = "Import prompts from a bundle, a prompt file or a directory")]
+    Import(ImportArgs),
 }
 
-pub async fn handle_prompts(config: Config, args: PromptsArgs) -> Result<()> {
+pub async fn handle_prompts(config: Config, args: PromptsArgs, output: OutputFormat) -> Result<()> {
     match args.cmd {
         Command::Create => {
             let name = dialoguer::Input::<String>::new()
                 .with_prompt("Name of the prompt")
+                .validate_with(|name: &String| {
+                    if template::is_valid_prompt_name(name) {
+                        Ok(())
+                    } else {
+                        Err("use letters, digits, '_', '-' and '.', not starting with '.'")
+                    }
+                })
                 .interact()?;
 
-            if config.load_prompt(name.as_str()).is_some() {
+            if config.find_prompt(&name).is_some() {
                 println!("{} Prompt already exists", style("✖").red());
                 Err(Error::PromptAlreadyExists { name: name.clone() })?;
             }
 
-            if let Some(new_content) = Editor::new().edit("").unwrap() {
-                config.save_prompt(&name, new_content.as_str())?;
+            if let Some(new_content) = Editor::new().edit("").map_err(|e| Error::EditorFailed {
+                reason: e.to_string(),
+            })? {
+                config.save_prompt(&parse_prompt(&name, &new_content)?)?;
                 println!("{} Prompt created", style("✔").green());
             }
         }
         Command::Edit(args) => {
-            let prompt = match config.load_prompt(&args.name) {
+            let prompt = match config.load_prompt(&args.name)? {
                 Some(prompt) => prompt,
                 None => Err(Error::PromptNotFound {
                     name: args.name.clone(),
                 })?,
             };
 
-            if let Some(new_content) = Editor::new().edit(&prompt.content).unwrap() {
-                config.save_prompt(&prompt.name, new_content.as_str())?;
+            // The whole file is edited, so the description, variable defaults
+            // and settings in the front matter can be changed too.
+            let document = prompt.to_markdown()?;
+            if let Some(new_document) =
+                Editor::new()
+                    .extension(".md")
+                    .edit(&document)
+                    .map_err(|e| Error::EditorFailed {
+                        reason: e.to_string(),
+                    })?
+            {
+                config.save_prompt(&Prompt::from_markdown(&prompt.name, &new_document)?)?;
                 println!("{} Prompt modified", style("✔").green());
             }
         }
-        Command::List => {
+        Command::List(args) if args.source => list_sources(&config, output)?,
+        Command::List(_) if output == OutputFormat::Json => {
+            // Prompts are written the same way they were stored before Markdown.
+            let prompts = config.load_prompts()?;
+            println!("{}", serde_json::to_string_pretty(&prompts)?);
+        }
+        Command::List(_) => {
             let mut table = Table::new();
             table
                 .set_header(vec!["Name", "Content", "Variables"])
@@ -83,14 +248,316 @@ pub async fn handle_prompts(config: Config, args: PromptsArgs) -> Result<()> {
         }
         Command::Delete(args) => {
             let name = args.name;
-            if config.load_prompt(&name).is_none() {
+            if config.find_prompt(&name).is_none() {
                 Err(Error::PromptNotFound { name: name.clone() })?;
             }
 
             config.delete_prompt(&name)?;
             println!("{} Prompt deleted", style("✔").green());
         }
+        Command::Settings(args) => {
+            let mut prompt = match config.load_prompt(&args.name)? {
+                Some(prompt) => prompt,
+                None => Err(Error::PromptNotFound {
+                    name: args.name.clone(),
+                })?,
+            };
+
+            let mut settings = if args.reset {
+                PromptSettings::default()
+            } else {
+                prompt.settings.clone()
+            };
+            let changed = args.reset
+                || args.model.is_some()
+                || args.temperature.is_some()
+                || args.backend.is_some()
+                || args.max_tokens.is_some()
+                || args.output.is_some();
+
+            settings.model = args.model.or(settings.model);
+            settings.temperature = args.temperature.or(settings.temperature);
+            settings.backend = args.backend.or(settings.backend);
+            settings.max_tokens = args.max_tokens.or(settings.max_tokens);
+            settings.output = args.output.or(settings.output);
+
+            if changed {
+                prompt.settings = settings.clone();
+                config.save_prompt(&prompt)?;
+                output.message(format!("{} Prompt settings updated", style("✔").green()));
+            }
+
+            println!("{}", serde_json::to_string_pretty(&settings)?);
+        }
+        Command::Test(args) => handle_test(&config, args, output).await?,
+        Command::History(args) => {
+            let versions = config.prompt_history(&args.name)?;
+            let mut table = Table::new();
+            table
+                .set_header(vec!["Version", "Saved"])
+                .load_preset(UTF8_FULL)
+                .apply_modifier(UTF8_ROUND_CORNERS);
+
+            for version in versions {
+                table.add_row(&[
+                    version.number.to_string(),
+                    version.saved_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                ]);
+            }
+            table.add_row([CURRENT, ""]);
+            println!("{}", table);
+        }
+        Command::Diff(args) => {
+            let from = load_version(&config, &args.name, &args.from)?.to_markdown()?;
+            let to = load_version(&config, &args.name, &args.to)?.to_markdown()?;
+            print_diff(
+                &format!("{} {}", args.name, args.from),
+                &format!("{} {}", args.name, args.to),
+                &from,
+                &to,
+            );
+        }
+        Command::Rollback(args) => {
+            let prompt = load_version(&config, &args.name, &args.version)?;
+            // Saving keeps the current version, so a rollback can be undone.
+            config.save_prompt(&prompt)?;
+            println!(
+                "{} Prompt restored to version {}",
+                style("✔").green(),
+                args.version
+            );
+        }
+        Command::Export(args) => {
+            let prompts = if args.names.is_empty() {
+                config.load_prompts()?
+            } else {
+                let prompts = args
+                    .names
+                    .into_iter()
+                    .map(|name| {
+                        config
+                            .load_prompt(&name)?
+                            .ok_or(Error::PromptNotFound { name })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                with_dependencies(&config, prompts)?
+            };
+
+            let bundle = Bundle::new(&prompts)?.to_yaml()?;
+            match args.output {
+                Some(path) => {
+                    fs::write(&path, bundle)?;
+                    eprintln!(
+                        "{} Exported {} prompts to {}",
+                        style("✔").green(),
+                        prompts.len(),
+                        path.display()
+                    );
+                }
+                None => print!("{}", bundle),
+            }
+        }
+        Command::Import(args) => import_prompts(&config, args)?,
+        Command::Render(args) => {
+            let input = args.input.unwrap_or_default();
+            let (prompt, values) = resolve_prompt(
+                &config,
+                Some(args.name),
+                args.set,
+                "",
+                &input,
+                AskFor::from_flag(args.interactive),
+            )?;
+
+            let template = prompt.template;
+            let rendered = template.render(&values)?;
+            let highlighted =
+                template.render_highlighted(&values, &|value| style(value).cyan().to_string())?;
+
+            println!("{}", highlighted);
+
+            // Examples are sent as previous turns of the conversation.
+            let mut tokens = estimate_tokens(&rendered);
+            for (i, example) in prompt.examples.iter().enumerate() {
+                println!("{}", style(format!("── Example {} input", i + 1)).dim());
+                println!("{}", example.input.trim_end());
+                println!("{}", style(format!("── Example {} output", i + 1)).dim());
+                println!("{}", example.output.trim_end());
+                tokens += estimate_tokens(&example.input) + estimate_tokens(&example.output);
+            }
+
+            eprintln!("{} ~{} tokens (estimated)", style("ℹ").blue(), tokens);
+        }
+    }
+
+    Ok(())
+}
+
+/// Loads a version of a prompt by its number in the history, or `current`.
+fn load_version(config: &Config, name: &str, version: &str) -> Result<Prompt> {
+    if version == CURRENT {
+        return config.load_prompt(name)?.ok_or(Error::PromptNotFound {
+            name: name.to_string(),
+        });
+    }
+
+    let found = config
+        .prompt_history(name)?
+        .into_iter()
+        .find(|v| v.number.to_string() == version);
+    match found {
+        Some(found) => config.load_prompt_version(name, &found),
+        None => Err(Error::PromptVersionNotFound {
+            name: name.to_string(),
+            version: version.to_string(),
+        }),
+    }
+}
+
+fn print_diff(from_label: &str, to_label: &str, from: &str, to: &str) {
+    if from == to {
+        println!("{} No differences", style("ℹ").blue());
+        return;
+    }
+
+    let diff = TextDiff::from_lines(from, to);
+    println!("{}", style(format!("--- {}", from_label)).red());
+    println!("{}", style(format!("+++ {}", to_label)).green());
+    for hunk in diff.unified_diff().iter_hunks() {
+        println!("{}", style(hunk.header()).cyan());
+        for change in hunk.iter_changes() {
+            let line = change.to_string_lossy();
+            let line = line.trim_end_matches('\n');
+            match change.tag() {
+                ChangeTag::Delete => println!("{}", style(format!("-{}", line)).red()),
+                ChangeTag::Insert => println!("{}", style(format!("+{}", line)).green()),
+                ChangeTag::Equal => println!(" {}", line),
+            }
+        }
+    }
+}
+
+/// Adds the prompts that `prompts` extend or include, and theirs in turn, so
+/// the exported bundle works on its own. Missing ones are only reported, as
+/// they may be imported separately.
+fn with_dependencies(config: &Config, mut prompts: Vec<Prompt>) -> Result<Vec<Prompt>> {
+    let mut next = 0;
+    while next < prompts.len() {
+        let prompt = &prompts[next];
+        let template = template::Template::parse(&prompt.content)?;
+        let dependencies: Vec<String> = prompt
+            .extends
+            .iter()
+            .map(String::as_str)
+            .chain(template.includes())
+            .map(str::to_string)
+            .collect();
+        let needed_by = prompt.name.clone();
+
+        for name in dependencies {
+            if prompts.iter().any(|p| p.name == name) {
+                continue;
+            }
+            match config.load_prompt(&name)? {
+                Some(dependency) => {
+                    eprintln!(
+                        "{} Including {}, used by {}",
+                        style("+").green(),
+                        name,
+                        needed_by
+                    );
+                    prompts.push(dependency);
+                }
+                None => eprintln!(
+                    "{} Prompt {} used by {} not found, it won't be exported",
+                    style("!").yellow(),
+                    name,
+                    needed_by
+                ),
+            }
+        }
+        next += 1;
+    }
+
+    Ok(prompts)
+}
+
+fn import_prompts(config: &Config, args: ImportArgs) -> Result<()> {
+    let mut imported = 0;
+    for mut prompt in bundle::load_prompts(&args.source)? {
+        let exists = config.find_prompt(&prompt.name).is_some();
+        let action = match (exists, args.on_conflict) {
+            (false, _) => style("new").green(),
+            (true, Conflict::Skip) => {
+                println!(
+                    "{} {} already exists, skipped",
+                    style("-").dim(),
+                    prompt.name
+                );
+                continue;
+            }
+            (true, Conflict::Overwrite) => style("overwritten").yellow(),
+            (true, Conflict::Rename) => {
+                let original = prompt.name.clone();
+                prompt.name = (2..)
+                    .map(|n| format!("{}-{}", original, n))
+                    .find(|name| config.find_prompt(name).is_none())
+                    .unwrap_or(original);
+                style("renamed").cyan()
+            }
+        };
+
+        println!("{} {} ({})", style("+").green(), prompt.name, action);
+        if !args.dry_run {
+            config.save_prompt(&prompt)?;
+        }
+        imported += 1;
+    }
+
+    if args.dry_run {
+        println!("{} Dry run, nothing was imported", style("ℹ").blue());
+    } else {
+        println!("{} Imported {} prompts", style("✔").green(), imported);
+    }
+    Ok(())
+}
+
+/// Lists the prompts of every directory in the search path, in order of
+/// precedence, marking the ones shadowed by a prompt with the same name.
+fn list_sources(config: &Config, output: OutputFormat) -> Result<()> {
+    let mut sources = Vec::new();
+    let mut active = HashMap::new();
+    for (dir, prompts) in config.load_prompts_by_source()? {
+        for prompt in prompts {
+            let status = match active.get(&prompt.name) {
+                Some(kind) => format!("shadowed by {}", kind),
+                None => "active".to_string(),
+            };
+            active.entry(prompt.name.clone()).or_insert(dir.kind);
+            sources.push(PromptSource {
+                name: prompt.name,
+                source: dir.kind.to_string(),
+                path: dir.path.display().to_string(),
+                status,
+            });
+        }
+    }
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&sources)?);
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table
+        .set_header(vec!["Name", "Source", "Path", "Status"])
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_content_arrangement(ContentArrangement::Dynamic);
+    for source in sources {
+        table.add_row([source.name, source.source, source.path, source.status]);
     }
 
+    println!("{}", table);
     Ok(())
 }
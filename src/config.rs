@@ -1,106 +1,813 @@
-use crate::Result;
-use regex::Regex;
+use crate::{
+    backend::Backend,
+    builtins,
+    template::{self, Template},
+    Error, Result,
+};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use derive_more::Display;
+use dialoguer::console::style;
 use serde::{Deserialize, Serialize};
 use std::{
-    fs,
+    collections::BTreeMap,
+    env, fs,
     path::{Path, PathBuf},
 };
 
 const SHELLDON: &str = "shelldon";
+const CONFIG_FILE: &str = "config.toml";
+const PROJECT_PROMPTS_DIR: &str = ".shelldon/prompts";
+const PROMPTS_PATH_ENV: &str = "SHELLDON_PROMPTS_PATH";
+const HISTORY_DIR: &str = "history";
+const VERSION_FORMAT: &str = "%Y%m%dT%H%M%S%.6fZ";
+const MARKDOWN: &str = "md";
+const LEGACY_JSON: &str = "json";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PromptValue {
     pub name: String,
-    pub value: String,
+    /// Default value, `None` when the prompt doesn't set one: the variable
+    /// is required, or only used in `#if` or `#each`.
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+/// An input with the answer expected for it, sent to the model as a previous
+/// turn of the conversation so it follows the same format.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Example {
+    pub input: String,
+    pub output: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Render the answer as markdown in the terminal
+    Markdown,
+    /// Print the answer as plain text
+    Raw,
+    /// Print only the code blocks of the answer
+    Code,
+}
+
+/// Preferred completion settings for a prompt, used unless the command line
+/// overrides them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PromptSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_temperature"
+    )]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<Backend>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputMode>,
+}
+
+impl PromptSettings {
+    /// Fills the settings that aren't set with the ones from `base`.
+    pub fn or(self, base: PromptSettings) -> Self {
+        Self {
+            model: self.model.or(base.model),
+            temperature: self.temperature.or(base.temperature),
+            backend: self.backend.or(base.backend),
+            max_tokens: self.max_tokens.or(base.max_tokens),
+            output: self.output.or(base.output),
+        }
+    }
+}
+
+/// Writes the temperature as the shortest decimal that reads back the same,
+/// so formats that only have 64-bit floats show `0.7` instead of `0.699999988079071`.
+fn serialize_temperature<S: serde::Serializer>(
+    temperature: &Option<f32>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    temperature
+        .map(|t| t.to_string().parse::<f64>().unwrap_or(t.into()))
+        .serialize(serializer)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Prompt {
     pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Prompt whose content comes before this one, and whose variable
+    /// defaults and settings this one inherits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
     pub content: String,
     pub values: Vec<PromptValue>,
+    #[serde(flatten)]
+    pub settings: PromptSettings,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<Example>,
+    /// Front matter syntax to use when the prompt is written back.
+    #[serde(skip)]
+    pub front_matter: FrontMatterFormat,
+}
+
+/// Syntax of the front matter block at the top of a Markdown prompt file,
+/// `---` fenced YAML or `+++` fenced TOML.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrontMatterFormat {
+    #[default]
+    Yaml,
+    Toml,
+}
+
+impl FrontMatterFormat {
+    fn delimiter(self) -> &'static str {
+        match self {
+            Self::Yaml => "---",
+            Self::Toml => "+++",
+        }
+    }
+}
+
+/// Everything about a prompt except its content, as written in the front matter.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct FrontMatter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    extends: Option<String>,
+    #[serde(flatten)]
+    settings: PromptSettings,
+    /// Defaults that override the ones written inline in the content or
+    /// inherited from the prompts it extends or includes.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    variables: BTreeMap<String, Scalar>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    examples: Vec<Example>,
+}
+
+/// A variable default as written by hand, so `count: 3` or `verbose = true`
+/// work without quoting.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Scalar {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl From<String> for Scalar {
+    /// Keeps values that read back the same unquoted, so `3` isn't written as `'3'`.
+    fn from(value: String) -> Self {
+        if let Some(scalar) = value
+            .parse()
+            .ok()
+            .map(Self::Bool)
+            .or_else(|| value.parse().ok().map(Self::Int))
+            .or_else(|| value.parse().ok().map(Self::Float))
+            .filter(|scalar| scalar.to_string() == value)
+        {
+            return scalar;
+        }
+
+        Self::String(value)
+    }
+}
+
+impl std::fmt::Display for Scalar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Float(value) => write!(f, "{}", value),
+            Self::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl Prompt {
+    /// Parses a Markdown prompt file. The content is everything after the
+    /// front matter, which is optional.
+    pub fn from_markdown(name: &str, text: &str) -> Result<Self> {
+        let Some((format, front_matter, content)) = split_front_matter(text) else {
+            return parse_prompt(name, text);
+        };
+
+        let invalid = |reason: String| Error::InvalidPrompt {
+            name: name.to_string(),
+            reason,
+        };
+        let front_matter: FrontMatter = match format {
+            _ if front_matter.trim().is_empty() => FrontMatter::default(),
+            FrontMatterFormat::Yaml => {
+                serde_yaml::from_str(front_matter).map_err(|e| invalid(e.to_string()))?
+            }
+            FrontMatterFormat::Toml => {
+                toml::from_str(front_matter).map_err(|e| invalid(e.to_string()))?
+            }
+        };
+
+        let mut prompt = parse_prompt(name, content)?;
+        for (name, default) in front_matter.variables {
+            match prompt.values.iter_mut().find(|value| value.name == name) {
+                Some(value) => value.value = Some(default.to_string()),
+                None => prompt.values.push(PromptValue {
+                    name,
+                    value: Some(default.to_string()),
+                }),
+            }
+        }
+        prompt.description = front_matter.description;
+        prompt.extends = front_matter.extends;
+        prompt.settings = front_matter.settings;
+        prompt.examples = front_matter.examples;
+        prompt.front_matter = format;
+        Ok(prompt)
+    }
+
+    /// Writes the prompt as Markdown with front matter. Only the variable
+    /// defaults that differ from the inline ones are listed.
+    pub fn to_markdown(&self) -> Result<String> {
+        let inline = parse_prompt(&self.name, &self.content)?;
+        let variables = self
+            .values
+            .iter()
+            .filter(|value| {
+                let inline_value = inline.values.iter().find(|v| v.name == value.name);
+                value.value.is_some() && inline_value.map(|v| &v.value) != Some(&value.value)
+            })
+            .filter_map(|value| Some((value.name.clone(), Scalar::from(value.value.clone()?))))
+            .collect();
+
+        let front_matter = FrontMatter {
+            name: Some(self.name.clone()),
+            description: self.description.clone(),
+            extends: self.extends.clone(),
+            settings: self.settings.clone(),
+            variables,
+            examples: self.examples.clone(),
+        };
+
+        let invalid = |reason: String| Error::InvalidPrompt {
+            name: self.name.clone(),
+            reason,
+        };
+        let front_matter = match self.front_matter {
+            FrontMatterFormat::Yaml => {
+                serde_yaml::to_string(&front_matter).map_err(|e| invalid(e.to_string()))?
+            }
+            FrontMatterFormat::Toml => {
+                toml::to_string(&front_matter).map_err(|e| invalid(e.to_string()))?
+            }
+        };
+
+        let delimiter = self.front_matter.delimiter();
+        Ok(format!(
+            "{}\n{}{}\n\n{}",
+            delimiter, front_matter, delimiter, self.content
+        ))
+    }
+}
+
+/// Where a prompt directory in the search path comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum SourceKind {
+    /// A `.shelldon/prompts` directory in the current directory or a parent.
+    #[display(fmt = "project")]
+    Project,
+    /// A directory listed in `SHELLDON_PROMPTS_PATH`.
+    #[display(fmt = "env")]
+    Env,
+    /// A directory listed in `prompts_path` of the config file.
+    #[display(fmt = "config")]
+    Config,
+    /// The user prompts directory.
+    #[display(fmt = "user")]
+    User,
+}
+
+#[derive(Debug, Clone)]
+pub struct PromptDir {
+    pub kind: SourceKind,
+    pub path: PathBuf,
+}
+
+/// Settings read from `config.toml` in the configuration directory.
+#[derive(Deserialize, Debug, Default)]
+struct Settings {
+    #[serde(default)]
+    prompts_path: Vec<PathBuf>,
+}
+
+/// A previous version of a prompt, numbered from the oldest.
+#[derive(Debug)]
+pub struct PromptVersion {
+    pub number: usize,
+    pub saved_at: DateTime<Local>,
+    path: PathBuf,
 }
 
 pub struct Config {
     config_dir: PathBuf,
     prompts_dir: PathBuf,
+    /// Directories searched for prompts, highest precedence first. A prompt
+    /// shadows the prompts with the same name in the directories after it.
+    search_path: Vec<PromptDir>,
 }
 
 impl Config {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
         let config_dir = dirs::config_dir()
-            .expect("Could not find configuration directory")
+            .ok_or(Error::ConfigDirNotFound)?
             .join(SHELLDON);
 
+        let config_file = config_dir.join(CONFIG_FILE);
+        let settings: Settings = match fs::read_to_string(&config_file) {
+            Ok(text) => toml::from_str(&text).map_err(|e| Error::InvalidConfig {
+                path: config_file.display().to_string(),
+                reason: e.message().trim().replace('\n', ", "),
+            })?,
+            Err(_) => Settings::default(),
+        };
+
         let prompts_dir = config_dir.join("prompts");
-        Self {
+        let mut search_path = project_prompt_dirs();
+        if let Some(paths) = env::var_os(PROMPTS_PATH_ENV) {
+            search_path.extend(
+                env::split_paths(&paths)
+                    .filter(|path| !path.as_os_str().is_empty())
+                    .map(|path| PromptDir {
+                        kind: SourceKind::Env,
+                        path,
+                    }),
+            );
+        }
+        search_path.extend(settings.prompts_path.into_iter().map(|path| PromptDir {
+            kind: SourceKind::Config,
+            // Relative paths are relative to the configuration directory.
+            path: config_dir.join(path),
+        }));
+        search_path.push(PromptDir {
+            kind: SourceKind::User,
+            path: prompts_dir.clone(),
+        });
+
+        Ok(Self {
             config_dir,
             prompts_dir,
-        }
+            search_path,
+        })
     }
 
-    pub fn initialize(&self) {
-        self.ensure_dir_exists(&self.config_dir);
-        self.ensure_dir_exists(&self.prompts_dir);
+    pub fn initialize(&self) -> Result<()> {
+        self.ensure_dir_exists(&self.config_dir)?;
+        self.ensure_dir_exists(&self.prompts_dir)
     }
 
-    fn ensure_dir_exists(&self, path: &Path) {
+    fn ensure_dir_exists(&self, path: &Path) -> Result<()> {
         if !path.exists() {
-            fs::create_dir_all(path).expect("Failed to create directory");
+            fs::create_dir_all(path)?;
         }
+        Ok(())
     }
 
-    pub fn save_prompt(&self, name: &str, content: &str) -> Result<()> {
-        let mut prompt_dir = self.prompts_dir.join(name);
-        prompt_dir.set_extension("json");
-        let prompt = parse_prompt(name, content)?;
-        let prompt_json = serde_json::to_string_pretty(&prompt)?;
-        fs::write(prompt_dir, prompt_json)?;
+    /// Saves a prompt where it was loaded from, or in the user directory
+    /// when it's new. The previous version is kept in the prompt history.
+    pub fn save_prompt(&self, prompt: &Prompt) -> Result<()> {
+        if !template::is_valid_prompt_name(&prompt.name) {
+            Err(Error::InvalidPromptName {
+                name: prompt.name.clone(),
+            })?
+        }
+
+        let existing = self.find_prompt(&prompt.name);
+        let dir = existing
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| self.prompts_dir.clone());
+
+        let document = prompt.to_markdown()?;
+        if let Some(existing) = existing {
+            if fs::read_to_string(&existing)? == document {
+                return Ok(());
+            }
+            save_version(&self.history_dir(&dir, &prompt.name), &existing)?;
+        }
+
+        fs::write(prompt_path(&dir, &prompt.name, MARKDOWN), document)?;
+
+        // Prompts saved by older versions are migrated to Markdown.
+        let legacy = prompt_path(&dir, &prompt.name, LEGACY_JSON);
+        if legacy.exists() {
+            fs::remove_file(legacy)?;
+        }
         Ok(())
     }
 
+    /// Deletes the prompt that `load_prompt` would return, which can reveal
+    /// a prompt it was shadowing.
     pub fn delete_prompt(&self, name: &str) -> Result<()> {
-        let mut prompt_dir = self.prompts_dir.join(name);
-        prompt_dir.set_extension("json");
-        fs::remove_file(prompt_dir)?;
+        let Some(dir) = self
+            .find_prompt(name)
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+        else {
+            Err(Error::PromptNotFound {
+                name: name.to_string(),
+            })?
+        };
+
+        for extension in [MARKDOWN, LEGACY_JSON] {
+            let path = prompt_path(&dir, name, extension);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        let history_dir = self.history_dir(&dir, name);
+        if history_dir.is_dir() {
+            fs::remove_dir_all(history_dir)?;
+        }
         Ok(())
     }
 
-    pub fn load_prompt(&self, name: &str) -> Option<Prompt> {
-        let mut prompt_dir = self.prompts_dir.join(name);
-        prompt_dir.set_extension("json");
-        let prompt_json = fs::read_to_string(prompt_dir).ok()?;
-        serde_json::from_str(&prompt_json).unwrap()
+    pub fn load_prompt(&self, name: &str) -> Result<Option<Prompt>> {
+        self.find_prompt(name)
+            .map(|path| read_prompt(&path))
+            .transpose()
     }
 
+    /// Loads the prompts of every directory in the search path, keeping the
+    /// one with the highest precedence for each name.
     pub fn load_prompts(&self) -> Result<Vec<Prompt>> {
-        let mut prompts = Vec::new();
+        let mut prompts = BTreeMap::new();
+        for (_, dir_prompts) in self.load_prompts_by_source()? {
+            for prompt in dir_prompts {
+                prompts.entry(prompt.name.clone()).or_insert(prompt);
+            }
+        }
+
+        Ok(prompts.into_values().collect())
+    }
+
+    /// Loads the prompts of each directory in the search path, in order of
+    /// precedence, including the ones that are shadowed.
+    pub fn load_prompts_by_source(&self) -> Result<Vec<(&PromptDir, Vec<Prompt>)>> {
+        self.search_path
+            .iter()
+            .filter(|dir| dir.path.is_dir())
+            .map(|dir| Ok((dir, read_prompt_dir(&dir.path)?)))
+            .collect()
+    }
+
+    /// Previous versions of a prompt, oldest first.
+    pub fn prompt_history(&self, name: &str) -> Result<Vec<PromptVersion>> {
+        let Some(dir) = self
+            .find_prompt(name)
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+        else {
+            Err(Error::PromptNotFound {
+                name: name.to_string(),
+            })?
+        };
+
+        let mut paths = Vec::new();
+        let history_dir = self.history_dir(&dir, name);
+        if history_dir.is_dir() {
+            for entry in fs::read_dir(history_dir)? {
+                paths.push(entry?.path());
+            }
+        }
+        // Timestamps are written so that they sort by name.
+        paths.sort();
+
+        let versions = paths
+            .into_iter()
+            .filter_map(|path| {
+                let stem = path.file_stem()?.to_str()?;
+                let saved_at = NaiveDateTime::parse_from_str(stem, VERSION_FORMAT).ok()?;
+                Some((saved_at.and_utc().with_timezone(&Local), path))
+            })
+            .enumerate()
+            .map(|(index, (saved_at, path))| PromptVersion {
+                number: index + 1,
+                saved_at,
+                path,
+            })
+            .collect();
 
-        for entry in fs::read_dir(&self.prompts_dir)? {
-            let entry = entry?;
-            let prompt_json = fs::read_to_string(entry.path())?;
-            let prompt: Prompt = serde_json::from_str(&prompt_json)?;
-            prompts.push(prompt);
+        Ok(versions)
+    }
+
+    /// Where the previous versions of a prompt of `dir` are kept. History
+    /// lives in the config directory, so it doesn't end up in the project
+    /// repositories prompts are shared through, with one directory for each
+    /// prompts directory.
+    fn history_dir(&self, dir: &Path, name: &str) -> PathBuf {
+        let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let mut key = String::new();
+        for c in dir.to_string_lossy().chars() {
+            match c {
+                '%' => key.push_str("%25"),
+                '/' => key.push_str("%2F"),
+                '\\' => key.push_str("%5C"),
+                ':' => key.push_str("%3A"),
+                c => key.push(c),
+            }
         }
 
-        Ok(prompts)
+        self.config_dir.join(HISTORY_DIR).join(key).join(name)
+    }
+
+    pub fn load_prompt_version(&self, name: &str, version: &PromptVersion) -> Result<Prompt> {
+        read_prompt_as(&version.path, name)
+    }
+
+    /// Path of the file of a prompt in the first directory that has it.
+    pub fn find_prompt(&self, name: &str) -> Option<PathBuf> {
+        self.search_path.iter().find_map(|dir| {
+            [MARKDOWN, LEGACY_JSON]
+                .into_iter()
+                .map(|extension| prompt_path(&dir.path, name, extension))
+                .find(|path| path.exists())
+        })
     }
 }
 
-pub fn parse_prompt(name: &str, content: &str) -> Result<Prompt> {
-    let re = Regex::new(r"\{(\w+):(\w+)\}")?;
-    let mut values = Vec::new();
+/// `.shelldon/prompts` directories from the current directory up to the
+/// root, nearest first.
+fn project_prompt_dirs() -> Vec<PromptDir> {
+    let Ok(cwd) = env::current_dir() else {
+        return Vec::new();
+    };
 
-    for cap in re.captures_iter(content) {
-        values.push(PromptValue {
-            name: cap[1].to_string(),
-            value: cap[2].to_string(),
-        });
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_PROMPTS_DIR))
+        .filter(|path| path.is_dir())
+        .map(|path| PromptDir {
+            kind: SourceKind::Project,
+            path,
+        })
+        .collect()
+}
+
+fn prompt_path(dir: &Path, name: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(name);
+    path.set_extension(extension);
+    path
+}
+
+/// Reads the prompts of a directory, sorted by name.
+pub fn read_prompt_dir(dir: &Path) -> Result<Vec<Prompt>> {
+    let mut prompts = BTreeMap::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|e| e.to_str());
+        if !matches!(extension, Some(MARKDOWN | LEGACY_JSON)) {
+            continue;
+        }
+
+        // One broken file shouldn't hide every other prompt, so it's only reported.
+        let prompt = match read_prompt(&path) {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                eprintln!("{} Skipping prompt: {}", style("!").yellow(), e);
+                continue;
+            }
+        };
+
+        // A Markdown file takes precedence over a legacy one with the same name.
+        if extension == Some(MARKDOWN) || !prompts.contains_key(&prompt.name) {
+            prompts.insert(prompt.name.clone(), prompt);
+        }
+    }
+
+    Ok(prompts.into_values().collect())
+}
+
+/// Reads a prompt file, either Markdown or the legacy JSON format. The name
+/// of a Markdown prompt is always its file name.
+pub fn read_prompt(path: &Path) -> Result<Prompt> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    read_prompt_as(path, &name)
+}
+
+fn read_prompt_as(path: &Path, name: &str) -> Result<Prompt> {
+    let invalid = |reason: String| Error::InvalidPromptFile {
+        path: path.display().to_string(),
+        reason,
+    };
+
+    let text = fs::read_to_string(path)?;
+    if path.extension().and_then(|e| e.to_str()) == Some(LEGACY_JSON) {
+        let mut prompt: Prompt = serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+        prompt.name = name.to_string();
+        prompt.content = template::upgrade_legacy(&prompt.content)?;
+        return Ok(prompt);
+    }
+
+    Prompt::from_markdown(name, &text).map_err(|e| match e {
+        Error::InvalidPrompt { reason, .. } | Error::InvalidTemplate { reason } => invalid(reason),
+        e => e,
+    })
+}
+
+/// Copies the current file of a prompt to its history, named after the
+/// time it was replaced.
+fn save_version(history_dir: &Path, current: &Path) -> Result<()> {
+    fs::create_dir_all(history_dir)?;
+
+    let extension = current
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or(MARKDOWN);
+    let timestamp = Utc::now().format(VERSION_FORMAT);
+    fs::copy(
+        current,
+        history_dir.join(format!("{}.{}", timestamp, extension)),
+    )?;
+    Ok(())
+}
+
+/// Splits a leading `---` or `+++` fenced block from the rest of the text.
+fn split_front_matter(text: &str) -> Option<(FrontMatterFormat, &str, &str)> {
+    let format = [FrontMatterFormat::Yaml, FrontMatterFormat::Toml]
+        .into_iter()
+        .find(|format| text.starts_with(format.delimiter()))?;
+    let delimiter = format.delimiter();
+    let rest = &text[delimiter.len()..];
+    let rest = rest
+        .strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))?;
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            let content = &rest[offset + line.len()..];
+            // The blank line after the front matter isn't part of the content.
+            let content = content
+                .strip_prefix("\r\n")
+                .or_else(|| content.strip_prefix('\n'))
+                .unwrap_or(content);
+            return Some((format, &rest[..offset], content));
+        }
+        offset += line.len();
     }
 
+    None
+}
+
+pub fn parse_prompt(name: &str, content: &str) -> Result<Prompt> {
+    let values = Template::parse(content)?
+        .variables()
+        .into_iter()
+        .filter(|v| !builtins::is_builtin(&v.name))
+        // The empty default of a variable only used in `#if` or `#each` would
+        // override the one of a prompt this one extends or includes.
+        .map(|v| PromptValue {
+            name: v.name,
+            value: v.default.filter(|_| !v.implicit),
+        })
+        .collect();
+
     Ok(Prompt {
         name: name.to_string(),
+        description: None,
+        extends: None,
         content: content.to_string(),
         values,
+        settings: PromptSettings::default(),
+        examples: Vec::new(),
+        front_matter: FrontMatterFormat::default(),
     })
 }
+
+#[cfg(test)]
+impl Config {
+    /// A config with a single, empty prompts directory in a temporary
+    /// directory named after the test.
+    pub fn for_test(test: &str) -> Self {
+        let dir = env::temp_dir().join(format!("shelldon-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let prompts_dir = dir.join("prompts");
+        fs::create_dir_all(&prompts_dir).unwrap();
+
+        Self {
+            config_dir: dir,
+            prompts_dir: prompts_dir.clone(),
+            search_path: vec![PromptDir {
+                kind: SourceKind::User,
+                path: prompts_dir,
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupt_json_prompt_is_an_error() {
+        let config = Config::for_test("corrupt-json");
+        fs::write(config.prompts_dir.join("broken.json"), "{\"name\": ").unwrap();
+
+        let result = config.load_prompt("broken");
+        assert!(
+            matches!(result, Err(Error::InvalidPromptFile { ref path, .. }) if path.ends_with("broken.json"))
+        );
+    }
+
+    #[test]
+    fn corrupt_front_matter_is_an_error() {
+        let config = Config::for_test("corrupt-front-matter");
+        fs::write(
+            config.prompts_dir.join("broken.md"),
+            "---\ntemperature: [\n---\n\nHello",
+        )
+        .unwrap();
+
+        let result = config.load_prompt("broken");
+        assert!(matches!(result, Err(Error::InvalidPromptFile { .. })));
+    }
+
+    #[test]
+    fn corrupt_prompt_does_not_hide_the_others() {
+        let config = Config::for_test("corrupt-list");
+        fs::write(config.prompts_dir.join("broken.json"), "not json").unwrap();
+        fs::write(config.prompts_dir.join("good.md"), "Hello {name}").unwrap();
+
+        let names: Vec<String> = config
+            .load_prompts()
+            .unwrap()
+            .into_iter()
+            .map(|prompt| prompt.name)
+            .collect();
+        assert_eq!(names, ["good"]);
+    }
+
+    #[test]
+    fn history_is_kept_in_the_config_dir() {
+        let config = Config::for_test("history");
+        let mut prompt = Prompt::from_markdown("greet", "Hello {name}").unwrap();
+        config.save_prompt(&prompt).unwrap();
+        prompt.content = "Hi {name}".to_string();
+        config.save_prompt(&prompt).unwrap();
+
+        let versions = config.prompt_history("greet").unwrap();
+        assert_eq!(versions.len(), 1);
+        assert!(versions[0]
+            .path
+            .starts_with(config.config_dir.join(HISTORY_DIR)));
+
+        config.delete_prompt("greet").unwrap();
+        assert!(!versions[0].path.exists());
+    }
+
+    #[test]
+    fn conditional_only_variable_has_no_default() {
+        let prompt = parse_prompt("child", "{#if lang}in {lang}{/if} {tone:formal}").unwrap();
+
+        let values: Vec<_> = prompt
+            .values
+            .iter()
+            .map(|v| (v.name.as_str(), v.value.as_deref()))
+            .collect();
+        assert_eq!(values, [("lang", None), ("tone", Some("formal"))]);
+    }
+
+    #[test]
+    fn missing_prompt_is_not_an_error() {
+        let config = Config::for_test("missing");
+        assert!(config.load_prompt("nothing").unwrap().is_none());
+    }
+
+    #[test]
+    fn prompt_names_stay_in_the_prompts_dir() {
+        let config = Config::for_test("names");
+        for name in ["../x", "a/b", ".hidden", ""] {
+            let prompt = parse_prompt(name, "Hello").unwrap();
+            assert!(matches!(
+                config.save_prompt(&prompt),
+                Err(Error::InvalidPromptName { .. })
+            ));
+        }
+        assert!(!config.config_dir.join("x.md").exists());
+
+        config
+            .save_prompt(&parse_prompt("fix-typos_v1.2", "Hello").unwrap())
+            .unwrap();
+        assert!(config.load_prompt("fix-typos_v1.2").unwrap().is_some());
+    }
+}
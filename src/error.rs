@@ -1,19 +1,113 @@
 use derive_more::{Display, From};
+use regex::Regex;
+use std::io;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// Exit codes, kept above the ones shells and clap use for their own errors.
+// A command that fails under `exec` exits with the command's own status.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_PROMPT_NOT_FOUND: i32 = 10;
+const EXIT_API_KEY_NOT_SET: i32 = 11;
+const EXIT_AUTH_FAILED: i32 = 12;
+const EXIT_RATE_LIMITED: i32 = 13;
+const EXIT_NETWORK: i32 = 14;
+const EXIT_EMPTY_RESPONSE: i32 = 15;
+const EXIT_ABORTED: i32 = 16;
+/// Like a shell reports a command stopped by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
 #[derive(Debug, Display, From)]
 pub enum Error {
     #[display(fmt = "Prompt '{}' already exists", name)]
     PromptAlreadyExists { name: String },
     #[display(fmt = "Prompt '{}' not found", name)]
     PromptNotFound { name: String },
-    #[display(fmt = "Command '{}' failed", command)]
-    CommandFailed { command: String },
+    #[display(fmt = "Prompt cycle detected: {}", chain)]
+    PromptCycle { chain: String },
+    #[display(fmt = "Version '{}' of prompt '{}' not found", version, name)]
+    PromptVersionNotFound { name: String, version: String },
+    #[display(
+        fmt = "Missing value for prompt variable '{}', set it with --set {}=<value>",
+        name,
+        name
+    )]
+    MissingPromptValue { name: String },
+    #[display(fmt = "Unknown prompt variable '{}'{}", name, suggestion)]
+    UnknownPromptValue { name: String, suggestion: String },
+    #[display(
+        fmt = "Invalid value '{}' for '{}', expected {}",
+        value,
+        name,
+        expected
+    )]
+    InvalidPromptValue {
+        name: String,
+        value: String,
+        expected: String,
+    },
+    #[display(fmt = "Invalid prompt '{}': {}", name, reason)]
+    InvalidPrompt { name: String, reason: String },
+    #[display(
+        fmt = "Invalid prompt name '{}', use letters, digits, '_', '-' and '.', not starting with '.'",
+        name
+    )]
+    InvalidPromptName { name: String },
+    #[display(fmt = "Invalid prompt file '{}': {}, fix or delete it", path, reason)]
+    InvalidPromptFile { path: String, reason: String },
+    #[display(fmt = "Invalid prompt bundle '{}': {}", path, reason)]
+    InvalidBundle { path: String, reason: String },
+    #[display(fmt = "Invalid prompt template: {}", reason)]
+    InvalidTemplate { reason: String },
+    #[display(fmt = "Command '{}' failed with exit status {}", command, status)]
+    CommandFailed { command: String, status: i32 },
+    #[display(fmt = "Aborted")]
+    Aborted,
+    #[display(fmt = "Interrupted")]
+    Interrupted,
+    #[display(fmt = "Could not find the configuration directory, set $HOME or $XDG_CONFIG_HOME")]
+    ConfigDirNotFound,
+    #[display(fmt = "Invalid config file '{}': {}", path, reason)]
+    InvalidConfig { path: String, reason: String },
+    #[display(fmt = "Could not open the editor ({}), set $VISUAL or $EDITOR", reason)]
+    EditorFailed { reason: String },
+    #[display(fmt = "{} is not supported on this platform yet", feature)]
+    UnsupportedPlatform { feature: String },
     #[display(fmt = "API key not set")]
     APIKeyNotSet,
     #[display(fmt = "Empty response")]
     EmptyResponse,
+    #[display(fmt = "Stream interrupted after ~{} tokens: {}", tokens, source)]
+    StreamInterrupted { tokens: usize, source: Box<Error> },
+    #[display(fmt = "'{}' is not a supported image (png, jpeg, gif or webp)", path)]
+    UnsupportedImage { path: String },
+    #[display(
+        fmt = "Image input is only supported with OpenAI models like gpt-4o, not '{}'",
+        model
+    )]
+    ImageInputNotSupported { model: String },
+    #[display(fmt = "The genai backend doesn't support image input, use --backend openai")]
+    GenAIImageInput,
+    #[display(fmt = "No code blocks found in the answer")]
+    NoCodeBlocks,
+    #[display(fmt = "Invalid JSON schema: {}", reason)]
+    InvalidSchema { reason: String },
+    #[display(fmt = "No valid JSON answer after {} attempts: {}", attempts, reason)]
+    InvalidJsonAnswer { attempts: usize, reason: String },
+    #[display(fmt = "No tests found for the prompt, expected them in '{}'", path)]
+    NoPromptTests { path: String },
+    #[display(fmt = "Invalid test file '{}': {}", path, reason)]
+    InvalidTestFile { path: String, reason: String },
+    #[display(fmt = "{} of {} prompt tests failed", failed, total)]
+    PromptTestsFailed { failed: usize, total: usize },
+    #[display(fmt = "File '{}' already exists", path)]
+    FileAlreadyExists { path: String },
+    #[display(fmt = "More than one code block would be written to '{}'", path)]
+    DuplicateFileName { path: String },
+    #[display(fmt = "No files match '{}'", pattern)]
+    FileNotFound { pattern: String },
+    #[display(fmt = "File '{}' exceeds the {} bytes limit", path, limit)]
+    FileTooLarge { path: String, limit: u64 },
 
     #[from]
     OpenAI(async_openai::error::OpenAIError),
@@ -25,6 +119,245 @@ pub enum Error {
     Serde(serde_json::Error),
     #[from]
     Dialoguer(dialoguer::Error),
+    /// Boxed, it's several times larger than every other variant.
+    GenAI(Box<genai::Error>),
     #[from]
-    GenAI(genai::Error),
+    Glob(glob::PatternError),
+}
+
+impl From<genai::Error> for Error {
+    fn from(error: genai::Error) -> Self {
+        Error::GenAI(Box::new(error))
+    }
+}
+
+impl Error {
+    /// A stable identifier of the error, for tools that read `--output json`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::PromptAlreadyExists { .. } => "prompt_already_exists",
+            Error::PromptNotFound { .. } => "prompt_not_found",
+            Error::PromptCycle { .. } => "prompt_cycle",
+            Error::PromptVersionNotFound { .. } => "prompt_version_not_found",
+            Error::MissingPromptValue { .. } => "missing_prompt_value",
+            Error::UnknownPromptValue { .. } => "unknown_prompt_value",
+            Error::InvalidPromptValue { .. } => "invalid_prompt_value",
+            Error::InvalidPrompt { .. } => "invalid_prompt",
+            Error::InvalidPromptName { .. } => "invalid_prompt_name",
+            Error::InvalidPromptFile { .. } => "invalid_prompt_file",
+            Error::InvalidBundle { .. } => "invalid_bundle",
+            Error::InvalidTemplate { .. } => "invalid_template",
+            Error::CommandFailed { .. } => "command_failed",
+            Error::Aborted => "aborted",
+            Error::Interrupted => "interrupted",
+            Error::ConfigDirNotFound => "config_dir_not_found",
+            Error::InvalidConfig { .. } => "invalid_config",
+            Error::EditorFailed { .. } => "editor_failed",
+            Error::UnsupportedPlatform { .. } => "unsupported_platform",
+            Error::APIKeyNotSet => "api_key_not_set",
+            Error::EmptyResponse => "empty_response",
+            Error::StreamInterrupted { .. } => "stream_interrupted",
+            Error::UnsupportedImage { .. } => "unsupported_image",
+            Error::ImageInputNotSupported { .. } => "image_input_not_supported",
+            Error::GenAIImageInput => "genai_image_input",
+            Error::NoCodeBlocks => "no_code_blocks",
+            Error::InvalidSchema { .. } => "invalid_schema",
+            Error::InvalidJsonAnswer { .. } => "invalid_json_answer",
+            Error::NoPromptTests { .. } => "no_prompt_tests",
+            Error::InvalidTestFile { .. } => "invalid_test_file",
+            Error::PromptTestsFailed { .. } => "prompt_tests_failed",
+            Error::FileAlreadyExists { .. } => "file_already_exists",
+            Error::DuplicateFileName { .. } => "duplicate_file_name",
+            Error::FileNotFound { .. } => "file_not_found",
+            Error::FileTooLarge { .. } => "file_too_large",
+            Error::OpenAI(_) => "openai",
+            Error::Io(_) => "io",
+            Error::Regex(_) => "regex",
+            Error::Serde(_) => "json",
+            Error::Dialoguer(_) => "terminal",
+            Error::GenAI(_) => "genai",
+            Error::Glob(_) => "glob",
+        }
+    }
+}
+
+impl Error {
+    /// The status the process exits with, so scripts can tell failures apart.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::PromptNotFound { .. } => EXIT_PROMPT_NOT_FOUND,
+            Error::APIKeyNotSet => EXIT_API_KEY_NOT_SET,
+            Error::EmptyResponse => EXIT_EMPTY_RESPONSE,
+            Error::StreamInterrupted { source, .. } => source.exit_code(),
+            Error::Aborted => EXIT_ABORTED,
+            Error::Interrupted => EXIT_INTERRUPTED,
+            Error::CommandFailed { status, .. } => *status,
+            Error::Dialoguer(dialoguer::Error::IO(e)) if e.kind() == io::ErrorKind::Interrupted => {
+                EXIT_ABORTED
+            }
+            Error::OpenAI(e) => openai_exit_code(e),
+            Error::GenAI(e) => genai_exit_code(e),
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+fn openai_exit_code(error: &async_openai::error::OpenAIError) -> i32 {
+    use async_openai::error::OpenAIError;
+
+    match error {
+        OpenAIError::ApiError(e) => {
+            let is =
+                |kind: &str| e.code.as_deref() == Some(kind) || e.r#type.as_deref() == Some(kind);
+            if is("invalid_api_key") || is("authentication_error") {
+                EXIT_AUTH_FAILED
+            } else if is("rate_limit_exceeded") {
+                EXIT_RATE_LIMITED
+            } else {
+                EXIT_FAILURE
+            }
+        }
+        // Streaming errors only keep the message, like "Invalid status code: 429 Too Many Requests".
+        OpenAIError::StreamError(message) => request_exit_code(
+            status_in(message, r"status code: (\d{3})"),
+            message.contains("error sending request"),
+        ),
+        OpenAIError::Reqwest(e) => request_exit_code(
+            e.status().map(|status| status.as_u16()),
+            e.is_connect() || e.is_timeout(),
+        ),
+        _ => EXIT_FAILURE,
+    }
+}
+
+fn genai_exit_code(error: &genai::Error) -> i32 {
+    match error {
+        genai::Error::ApiKeyEnvNotFound { .. } | genai::Error::AdapterRequiresApiKey { .. } => {
+            EXIT_API_KEY_NOT_SET
+        }
+        genai::Error::AdapterNoChatResponse => EXIT_EMPTY_RESPONSE,
+        genai::Error::Webc(_) | genai::Error::ReqwestEventSource(_) => {
+            genai_request_exit_code(&format!("{:?}", error))
+        }
+        _ => EXIT_FAILURE,
+    }
+}
+
+/// genai doesn't expose its HTTP errors, only their debug output, like
+/// `Webc(ResponseFailedStatus { status: 401, .. })`,
+/// `Webc(Reqwest(reqwest::Error { kind: Request, .. }))` or
+/// `ReqwestEventSource(InvalidStatusCode(429, ..))` when streaming.
+fn genai_request_exit_code(debug: &str) -> i32 {
+    request_exit_code(
+        status_in(debug, r"(?:status: |InvalidStatusCode\()(\d{3})"),
+        debug.contains("kind: Request"),
+    )
+}
+
+/// The HTTP status captured by `pattern` in an error message.
+fn status_in(text: &str, pattern: &str) -> Option<u16> {
+    Regex::new(pattern)
+        .ok()?
+        .captures(text)
+        .and_then(|captures| captures[1].parse().ok())
+}
+
+/// Exit code of a failed HTTP request to a provider.
+fn request_exit_code(status: Option<u16>, unreachable: bool) -> i32 {
+    match status {
+        Some(401 | 403) => EXIT_AUTH_FAILED,
+        Some(429) => EXIT_RATE_LIMITED,
+        None if unreachable => EXIT_NETWORK,
+        _ => EXIT_FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::error::{ApiError, OpenAIError};
+
+    fn api_error(code: &str) -> OpenAIError {
+        OpenAIError::ApiError(ApiError {
+            message: "failed".to_string(),
+            r#type: None,
+            param: None,
+            code: Some(code.to_string()),
+        })
+    }
+
+    #[test]
+    fn openai_errors_have_their_own_exit_codes() {
+        assert_eq!(
+            Error::OpenAI(api_error("invalid_api_key")).exit_code(),
+            EXIT_AUTH_FAILED
+        );
+        assert_eq!(
+            Error::OpenAI(api_error("rate_limit_exceeded")).exit_code(),
+            EXIT_RATE_LIMITED
+        );
+        assert_eq!(
+            Error::OpenAI(api_error("context_length_exceeded")).exit_code(),
+            EXIT_FAILURE
+        );
+    }
+
+    #[test]
+    fn openai_stream_errors_are_read_from_the_message() {
+        let exit_code = |message: &str| {
+            Error::OpenAI(OpenAIError::StreamError(message.to_string())).exit_code()
+        };
+        assert_eq!(
+            exit_code("Invalid status code: 429 Too Many Requests"),
+            EXIT_RATE_LIMITED
+        );
+        assert_eq!(
+            exit_code("Invalid status code: 401 Unauthorized"),
+            EXIT_AUTH_FAILED
+        );
+        assert_eq!(
+            exit_code("Transport error: error sending request for url (https://api.openai.com/v1/chat/completions)"),
+            EXIT_NETWORK
+        );
+    }
+
+    #[test]
+    fn genai_errors_have_their_own_exit_codes() {
+        let missing_key = genai::Error::ApiKeyEnvNotFound {
+            env_name: "OPENAI_API_KEY".to_string(),
+        };
+        assert_eq!(Error::from(missing_key).exit_code(), EXIT_API_KEY_NOT_SET);
+        assert_eq!(
+            Error::from(genai::Error::AdapterNoChatResponse).exit_code(),
+            EXIT_EMPTY_RESPONSE
+        );
+    }
+
+    // Debug output of genai 0.1.1 errors, which is all there is to go by.
+    // These break when an upgrade changes it.
+    #[test]
+    fn genai_request_errors_are_read_from_their_debug_output() {
+        assert_eq!(
+            genai_request_exit_code(
+                r#"Webc(ResponseFailedStatus { status: 401, body: "{\"error\": {}}" })"#
+            ),
+            EXIT_AUTH_FAILED
+        );
+        assert_eq!(
+            genai_request_exit_code(
+                r#"ReqwestEventSource(InvalidStatusCode(429, Response { url: "https://api.anthropic.com/v1/messages", status: 429, headers: {} }))"#
+            ),
+            EXIT_RATE_LIMITED
+        );
+        assert_eq!(
+            genai_request_exit_code(
+                r#"Webc(Reqwest(reqwest::Error { kind: Request, url: "https://api.openai.com/v1/chat/completions", source: hyper_util::client::legacy::Error(Connect, ConnectError("dns error", Custom { kind: Uncategorized, error: "failed to lookup address information" })) }))"#
+            ),
+            EXIT_NETWORK
+        );
+        assert_eq!(
+            genai_request_exit_code(r#"Webc(ResponseFailedStatus { status: 500, body: "" })"#),
+            EXIT_FAILURE
+        );
+    }
 }
@@ -0,0 +1,247 @@
+use crate::{Error, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// A fenced code block found in an answer.
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    pub lang: Option<String>,
+    pub filename: Option<String>,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// Path to write the block to, using the filename mentioned in the answer
+    /// when there is a safe one, or `block_<n>.<ext>` based on the language.
+    pub fn file_path(&self, index: usize) -> PathBuf {
+        if let Some(path) = self.filename.as_deref().and_then(safe_relative_path) {
+            return path;
+        }
+
+        PathBuf::from(format!("block_{}.{}", index + 1, self.extension()))
+    }
+
+    pub fn extension(&self) -> &str {
+        let lang = self.lang.as_deref().unwrap_or_default().to_lowercase();
+        match lang.as_str() {
+            "rust" | "rs" => "rs",
+            "python" | "py" => "py",
+            "bash" | "sh" | "shell" | "zsh" => "sh",
+            "javascript" | "js" => "js",
+            "typescript" | "ts" => "ts",
+            "yaml" | "yml" => "yaml",
+            "json" => "json",
+            "toml" => "toml",
+            "go" | "golang" => "go",
+            "html" => "html",
+            "css" => "css",
+            "sql" => "sql",
+            "c" => "c",
+            "cpp" | "c++" => "cpp",
+            "java" => "java",
+            "ruby" | "rb" => "rb",
+            "markdown" | "md" => "md",
+            "dockerfile" | "docker" => "dockerfile",
+            "nginx" | "conf" | "ini" => "conf",
+            "xml" => "xml",
+            _ => "txt",
+        }
+    }
+}
+
+/// Parses the fenced code blocks of a markdown answer.
+///
+/// A filename is taken from the fence info string (```` ```rust src/main.rs ````)
+/// or from the last line of prose before the block when it mentions one in
+/// backticks or bold, or ends with `name.ext:`.
+pub fn parse_code_blocks(text: &str) -> Result<Vec<CodeBlock>> {
+    let filename_re = Regex::new(
+        r"[`*]+([\w./-]+\.[A-Za-z][A-Za-z0-9]*|Dockerfile|Makefile)[`*]+|([\w./-]+\.[A-Za-z][A-Za-z0-9]*):\s*$",
+    )?;
+
+    let mut blocks = Vec::new();
+    let mut current: Option<CodeBlock> = None;
+    let mut last_prose = "";
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match (trimmed.strip_prefix("```"), current.take()) {
+            (Some(_), Some(block)) => {
+                blocks.push(block);
+                last_prose = "";
+            }
+            (Some(info), None) => {
+                let mut parts = info.split_whitespace();
+                let lang = parts.next().map(str::to_string);
+                let filename = parts
+                    .next()
+                    .map(str::to_string)
+                    .or_else(|| find_filename(&filename_re, last_prose));
+                current = Some(CodeBlock {
+                    lang,
+                    filename,
+                    code: String::new(),
+                });
+            }
+            (None, Some(mut block)) => {
+                block.code.push_str(line);
+                block.code.push('\n');
+                current = Some(block);
+            }
+            (None, None) => {
+                if !trimmed.is_empty() {
+                    last_prose = trimmed;
+                }
+            }
+        }
+    }
+
+    // An unterminated block at the end of the answer still counts.
+    if let Some(block) = current {
+        blocks.push(block);
+    }
+
+    Ok(blocks)
+}
+
+/// Works out where each block goes under `dir`, failing before anything is
+/// written when a file already exists or two blocks would share a path.
+///
+/// Blocks come with their index in the answer, which names the ones without
+/// a filename.
+pub fn target_paths(dir: &Path, blocks: &[(usize, &CodeBlock)]) -> Result<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut paths = Vec::with_capacity(blocks.len());
+
+    for (index, block) in blocks {
+        let path = dir.join(block.file_path(*index));
+        if !seen.insert(path.clone()) {
+            Err(Error::DuplicateFileName {
+                path: path.display().to_string(),
+            })?
+        }
+        if path.exists() {
+            Err(Error::FileAlreadyExists {
+                path: path.display().to_string(),
+            })?
+        }
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+fn find_filename(re: &Regex, line: &str) -> Option<String> {
+    re.captures_iter(line)
+        .filter_map(|cap| cap.get(1).or_else(|| cap.get(2)))
+        .last()
+        .map(|m| m.as_str().to_string())
+}
+
+fn safe_relative_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    let is_safe = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if is_safe && path.file_name().is_some() {
+        Some(path.to_path_buf())
+    } else {
+        path.file_name().map(PathBuf::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    fn block(filename: Option<&str>) -> CodeBlock {
+        CodeBlock {
+            lang: Some("rust".to_string()),
+            filename: filename.map(str::to_string),
+            code: "fn main() {}\n".to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_language_and_code() {
+        let blocks = parse_code_blocks("Run this:\n\n```bash\nls -la\necho hi\n```\n").unwrap();
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].lang.as_deref(), Some("bash"));
+        assert_eq!(blocks[0].code, "ls -la\necho hi\n");
+        assert_eq!(blocks[0].filename, None);
+    }
+
+    #[test]
+    fn takes_filename_from_fence_or_prose() {
+        let text = "```rust src/main.rs\nfn main() {}\n```\n\
+                    Then create `Cargo.toml`:\n```toml\n[package]\n```\n\
+                    And config.yaml:\n```yaml\na: 1\n```\n";
+        let blocks = parse_code_blocks(text).unwrap();
+
+        let names: Vec<_> = blocks.iter().map(|b| b.filename.as_deref()).collect();
+        assert_eq!(
+            names,
+            [Some("src/main.rs"), Some("Cargo.toml"), Some("config.yaml")]
+        );
+    }
+
+    #[test]
+    fn keeps_unterminated_block() {
+        let blocks = parse_code_blocks("```python\nprint(1)\n").unwrap();
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].code, "print(1)\n");
+    }
+
+    #[test]
+    fn file_path_falls_back_to_block_index() {
+        assert_eq!(block(None).file_path(1), PathBuf::from("block_2.rs"));
+    }
+
+    #[test]
+    fn file_path_stays_inside_target_dir() {
+        assert_eq!(
+            block(Some("../../etc/passwd")).file_path(0),
+            PathBuf::from("passwd")
+        );
+        assert_eq!(
+            block(Some("/tmp/evil.sh")).file_path(0),
+            PathBuf::from("evil.sh")
+        );
+        assert_eq!(
+            block(Some("src/lib.rs")).file_path(0),
+            PathBuf::from("src/lib.rs")
+        );
+    }
+
+    #[test]
+    fn target_paths_rejects_duplicate_names() {
+        let dir = env::temp_dir().join(format!("shelldon-extract-dup-{}", std::process::id()));
+        let (a, b) = (block(Some("main.rs")), block(Some("main.rs")));
+
+        let result = target_paths(&dir, &[(0, &a), (1, &b)]);
+        assert!(
+            matches!(result, Err(Error::DuplicateFileName { ref path }) if path.ends_with("main.rs"))
+        );
+    }
+
+    #[test]
+    fn target_paths_rejects_existing_files() {
+        let dir = env::temp_dir().join(format!("shelldon-extract-exists-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("block_2.rs"), "").unwrap();
+        let (a, b) = (block(Some("main.rs")), block(None));
+
+        let result = target_paths(&dir, &[(0, &a), (1, &b)]);
+        assert!(
+            matches!(result, Err(Error::FileAlreadyExists { ref path }) if path.ends_with("block_2.rs"))
+        );
+
+        let paths = target_paths(&dir, &[(0, &a)]).unwrap();
+        assert_eq!(paths, [dir.join("main.rs")]);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{Error, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{fs, path::Path};
+
+const SIGNATURES: [(&[u8], &str); 4] = [
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+];
+
+/// An image attached to the user message, identified by its magic bytes.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub media_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let media_type = detect_media_type(data)?;
+        Some(Self {
+            media_type,
+            data: data.to_vec(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        Self::from_bytes(&data).ok_or_else(|| Error::UnsupportedImage {
+            path: path.display().to_string(),
+        })
+    }
+
+    pub fn to_data_url(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.media_type,
+            STANDARD.encode(&self.data)
+        )
+    }
+}
+
+fn detect_media_type(data: &[u8]) -> Option<&'static str> {
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, media_type)| *media_type)
+}
@@ -0,0 +1,82 @@
+use crate::{Error, Result};
+use jsonschema::Validator;
+use serde_json::Value;
+
+/// Tells the model to answer with JSON only, matching `schema` if there is one.
+pub fn instructions(schema: Option<&Value>) -> String {
+    let mut instructions = String::from(
+        "Answer only with a valid JSON value, without any explanation or markdown code fences.",
+    );
+    if let Some(schema) = schema {
+        instructions.push_str(&format!(
+            " The JSON must be valid against this JSON schema:\n{}",
+            schema
+        ));
+    }
+    instructions
+}
+
+/// Whether the answer must be a JSON object, which is the case without a
+/// schema or when the schema's root `type` is `object`.
+pub fn expects_object(schema: Option<&Value>) -> bool {
+    schema.is_none_or(|schema| schema.get("type").and_then(Value::as_str) == Some("object"))
+}
+
+/// Parses a JSON answer, ignoring a markdown code fence around it since
+/// models add one even when asked not to.
+pub fn parse_answer(text: &str) -> serde_json::Result<Value> {
+    serde_json::from_str(strip_fence(text.trim()))
+}
+
+/// Checks `value` against a JSON schema and returns every violation, each
+/// with the path of the value it's about.
+pub fn validate(schema: &Value, value: &Value) -> Result<Vec<String>> {
+    let errors = validator(schema)?
+        .iter_errors(value)
+        .map(|error| match error.instance_path.to_string() {
+            path if path.is_empty() => error.to_string(),
+            path => format!("{}: {}", path, error),
+        })
+        .collect();
+    Ok(errors)
+}
+
+/// Checks that `schema` is itself a valid JSON schema.
+pub fn check_schema(schema: &Value) -> Result<()> {
+    validator(schema).map(|_| ())
+}
+
+fn validator(schema: &Value) -> Result<Validator> {
+    jsonschema::validator_for(schema).map_err(|e| Error::InvalidSchema {
+        reason: e.to_string(),
+    })
+}
+
+fn strip_fence(text: &str) -> &str {
+    let Some(rest) = text.strip_prefix("```") else {
+        return text;
+    };
+
+    // Skip the info string, like `json`, up to the end of the first line.
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn expects_object_without_schema_or_with_object_root() {
+        assert!(expects_object(None));
+        assert!(expects_object(Some(&json!({"type": "object"}))));
+    }
+
+    #[test]
+    fn other_schemas_do_not_expect_object() {
+        assert!(!expects_object(Some(&json!({"type": "array"}))));
+        assert!(!expects_object(Some(&json!({"type": ["object", "null"]}))));
+        assert!(!expects_object(Some(&json!({"enum": [1, 2]}))));
+    }
+}
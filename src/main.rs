@@ -1,19 +1,41 @@
 mod backend;
+mod builtins;
+mod bundle;
 mod command;
 mod config;
 mod error;
+mod extract;
+mod image;
+mod json;
+mod markdown;
 mod processor;
+mod redact;
+mod risk;
 mod system;
+mod template;
 
 use dialoguer::console::style;
 pub use error::{Error, Result};
 
 use clap::{Parser, Subcommand};
-use command::{handle_ask, handle_exec, handle_prompts, AskArgs, ExecArgs, PromptsArgs};
+use command::{
+    handle_ask, handle_compare, handle_exec, handle_prompts, AskArgs, CompareArgs, ExecArgs,
+    OutputFormat, PromptsArgs,
+};
 use config::Config;
 
 #[derive(Parser)]
 struct App {
+    #[clap(
+        id = "output_format",
+        long = "output",
+        value_name = "FORMAT",
+        global = true,
+        value_enum,
+        default_value = "text",
+        help = "Format of the results and errors"
+    )]
+    output: OutputFormat,
     #[clap(subcommand)]
     command: Commands,
 }
@@ -26,22 +48,61 @@ enum Commands {
     Prompts(PromptsArgs),
     #[clap(about = "Ask a question")]
     Ask(AskArgs),
+    #[clap(about = "Ask several models the same question and compare their answers")]
+    Compare(CompareArgs),
 }
 
 #[tokio::main]
 async fn main() {
-    let config = Config::new();
-    config.initialize();
-
     let app = App::parse();
-    let result = match app.command {
-        Commands::Exec(args) => handle_exec(config, args).await,
-        Commands::Prompts(args) => handle_prompts(config, args).await,
-        Commands::Ask(args) => handle_ask(config, args).await,
-    };
-
-    if let Err(e) = result {
-        eprintln!("{} {}", style("✖").red(), e);
-        std::process::exit(1);
+    let output = app.output;
+
+    if let Err(e) = run(app).await {
+        match output {
+            OutputFormat::Text => eprintln!("{} {}", style("✖").red(), e),
+            OutputFormat::Json => eprintln!(
+                "{}",
+                serde_json::json!({ "error": { "code": e.code(), "message": e.to_string() } })
+            ),
+        }
+        std::process::exit(e.exit_code());
+    }
+}
+
+async fn run(app: App) -> Result<()> {
+    let config = Config::new()?;
+    config.initialize()?;
+
+    match app.command {
+        Commands::Exec(args) => handle_exec(config, args, app.output).await,
+        Commands::Prompts(args) => handle_prompts(config, args, app.output).await,
+        Commands::Ask(args) => handle_ask(config, args, app.output).await,
+        Commands::Compare(args) => handle_compare(config, args, app.output).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn app_definition_is_valid() {
+        App::command().debug_assert();
+    }
+
+    #[test]
+    fn output_format_does_not_clash_with_subcommand_output() {
+        let app = App::try_parse_from([
+            "shelldon",
+            "prompts",
+            "export",
+            "-o",
+            "prompts.yaml",
+            "--output",
+            "json",
+        ])
+        .unwrap();
+        assert!(app.output == OutputFormat::Json);
     }
 }
@@ -0,0 +1,359 @@
+use crate::Result;
+use dialoguer::console::style;
+use regex::{Captures, Regex};
+use std::io::Write;
+use syntect::{
+    highlighting::{HighlightIterator, HighlightState, Highlighter, Theme, ThemeSet},
+    parsing::{ParseState, ScopeStack, SyntaxSet},
+    util::as_24_bit_terminal_escaped,
+};
+
+const THEME: &str = "base16-ocean.dark";
+
+/// Renders markdown for the terminal as it is streamed in.
+///
+/// Lines that can be headings, lists, quotes or code are buffered until they
+/// are complete, so each is rendered once with enough context to know what
+/// it is. Paragraph text is written as it arrives, up to the last word with
+/// no inline code, emphasis or link left open.
+pub struct MarkdownRenderer {
+    pending: String,
+    /// How much of the pending line was already written, once it's known to
+    /// be paragraph text.
+    streamed: Option<usize>,
+    syntaxes: SyntaxSet,
+    theme: Theme,
+    code_block: Option<CodeBlock>,
+    inline: InlineRules,
+}
+
+struct CodeBlock {
+    parse: ParseState,
+    highlight: HighlightState,
+}
+
+struct InlineRules {
+    code: Regex,
+    bold: Regex,
+    italic: Regex,
+    link: Regex,
+}
+
+impl MarkdownRenderer {
+    pub fn new() -> Result<Self> {
+        let theme = ThemeSet::load_defaults()
+            .themes
+            .remove(THEME)
+            .unwrap_or_default();
+
+        Ok(Self {
+            pending: String::new(),
+            streamed: None,
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme,
+            code_block: None,
+            inline: InlineRules {
+                code: Regex::new(r"`([^`]+)`")?,
+                bold: Regex::new(r"\*\*([^*]+)\*\*|__([^_]+)__")?,
+                italic: Regex::new(r"\*([^*\s][^*]*)\*")?,
+                link: Regex::new(r"\[([^\]]+)\]\(([^)]+)\)")?,
+            },
+        })
+    }
+
+    pub fn push(&mut self, chunk: &str, out: &mut impl Write) -> Result<()> {
+        self.pending.push_str(chunk);
+
+        while let Some(pos) = self.pending.find('\n') {
+            let line: String = self.pending.drain(..=pos).collect();
+            self.end_line(&line, out)?;
+        }
+
+        self.stream_partial_line(out)
+    }
+
+    pub fn finish(&mut self, out: &mut impl Write) -> Result<()> {
+        if !self.pending.is_empty() {
+            let mut line = std::mem::take(&mut self.pending);
+            line.push('\n');
+            self.end_line(&line, out)?;
+        }
+
+        self.code_block = None;
+        Ok(())
+    }
+
+    fn end_line(&mut self, line: &str, out: &mut impl Write) -> Result<()> {
+        match self.streamed.take() {
+            Some(streamed) => {
+                let rest = line[streamed..].trim_end();
+                writeln!(out, "{}", self.render_inline(rest))?;
+                Ok(())
+            }
+            None => self.render_line(line, out),
+        }
+    }
+
+    /// Writes what can already be rendered of an unfinished paragraph line.
+    fn stream_partial_line(&mut self, out: &mut impl Write) -> Result<()> {
+        if self.code_block.is_some() {
+            return Ok(());
+        }
+
+        let streamed = match self.streamed {
+            Some(streamed) => streamed,
+            None => {
+                let body = self.pending.trim_start();
+                // Wait for the rest of lines that may be anything but a paragraph.
+                match body.chars().next() {
+                    None => return Ok(()),
+                    Some(c) if "#-*+>`".contains(c) => return Ok(()),
+                    Some(_) => {}
+                }
+                let indent = self.pending.len() - body.len();
+                write!(out, "{}", &self.pending[..indent])?;
+                indent
+            }
+        };
+
+        let rest = &self.pending[streamed..];
+        let cut = rest
+            .char_indices()
+            .rev()
+            .filter(|(_, c)| c.is_whitespace())
+            .map(|(i, c)| i + c.len_utf8())
+            .find(|&i| spans_closed(&rest[..i]))
+            .unwrap_or(0);
+        if cut > 0 {
+            write!(out, "{}", self.render_inline(&rest[..cut]))?;
+        }
+
+        self.streamed = Some(streamed + cut);
+        out.flush()?;
+        Ok(())
+    }
+
+    fn render_line(&mut self, line: &str, out: &mut impl Write) -> Result<()> {
+        let trimmed = line.trim_start();
+
+        if let Some(lang) = trimmed.strip_prefix("```") {
+            if self.code_block.take().is_none() {
+                self.code_block = Some(self.start_code_block(lang.trim()));
+            }
+            writeln!(out, "{}", style(line.trim_end()).dim())?;
+            return Ok(());
+        }
+
+        if let Some(block) = self.code_block.as_mut() {
+            // Fall back to the plain line if the syntax definition can't parse it.
+            let Ok(ops) = block.parse.parse_line(line, &self.syntaxes) else {
+                write!(out, "{}", line)?;
+                return Ok(());
+            };
+
+            let highlighter = Highlighter::new(&self.theme);
+            let ranges: Vec<_> =
+                HighlightIterator::new(&mut block.highlight, &ops, line, &highlighter).collect();
+            let escaped = as_24_bit_terminal_escaped(&ranges, false);
+            writeln!(out, "{}\x1b[0m", escaped.trim_end_matches('\n'))?;
+            return Ok(());
+        }
+
+        let content = line.trim_end();
+        let indent = &content[..content.len() - content.trim_start().len()];
+        let body = content.trim_start();
+
+        if let Some((level, heading)) = parse_heading(body) {
+            let heading = style(heading).bold();
+            let heading = if level == 1 {
+                heading.underlined().magenta()
+            } else {
+                heading.magenta()
+            };
+            writeln!(out, "{}", heading)?;
+        } else if let Some(item) = body
+            .strip_prefix("- ")
+            .or_else(|| body.strip_prefix("* "))
+            .or_else(|| body.strip_prefix("+ "))
+        {
+            writeln!(
+                out,
+                "{}{} {}",
+                indent,
+                style("•").cyan(),
+                self.render_inline(item)
+            )?;
+        } else if let Some(quote) = body.strip_prefix('>') {
+            writeln!(out, "{} {}", style("│").dim(), style(quote.trim()).italic())?;
+        } else if body.chars().all(|c| c == '-' || c == '*') && body.len() >= 3 {
+            writeln!(out, "{}", style("─".repeat(40)).dim())?;
+        } else {
+            writeln!(out, "{}{}", indent, self.render_inline(body))?;
+        }
+
+        Ok(())
+    }
+
+    fn render_inline(&self, text: &str) -> String {
+        // Inline code is left untouched by the other rules.
+        let mut rendered = String::new();
+        let mut last = 0;
+        for m in self.inline.code.find_iter(text) {
+            rendered.push_str(&self.render_emphasis(&text[last..m.start()]));
+            let code = m.as_str().trim_matches('`');
+            rendered.push_str(&style(code).cyan().to_string());
+            last = m.end();
+        }
+
+        rendered.push_str(&self.render_emphasis(&text[last..]));
+        rendered
+    }
+
+    fn render_emphasis(&self, text: &str) -> String {
+        let rules = &self.inline;
+        let text = rules.link.replace_all(text, |c: &Captures| {
+            format!("{} ({})", style(&c[1]).underlined(), style(&c[2]).dim())
+        });
+        let text = rules
+            .bold
+            .replace_all(&text, |c: &Captures| {
+                let inner = c.get(1).or_else(|| c.get(2)).map_or("", |m| m.as_str());
+                style(inner).bold().to_string()
+            })
+            .to_string();
+        rules
+            .italic
+            .replace_all(&text, |c: &Captures| style(&c[1]).italic().to_string())
+            .to_string()
+    }
+
+    fn start_code_block(&self, lang: &str) -> CodeBlock {
+        let syntax = self
+            .syntaxes
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| self.syntaxes.find_syntax_plain_text());
+        let highlighter = Highlighter::new(&self.theme);
+
+        CodeBlock {
+            parse: ParseState::new(syntax),
+            highlight: HighlightState::new(&highlighter, ScopeStack::new()),
+        }
+    }
+}
+
+/// Whether every inline code span, emphasis and link in `text` is closed, so
+/// it renders the same on its own as along with the rest of the line.
+fn spans_closed(text: &str) -> bool {
+    if !text.matches('`').count().is_multiple_of(2) {
+        return false;
+    }
+
+    // Markers inside code spans are left as they are.
+    let outside: String = text.split('`').step_by(2).collect();
+    outside.matches("**").count().is_multiple_of(2)
+        && outside
+            .replace("**", "")
+            .matches('*')
+            .count()
+            .is_multiple_of(2)
+        && outside.matches("__").count().is_multiple_of(2)
+        && outside.matches('[').count() == outside.matches(']').count()
+        && outside.matches('(').count() == outside.matches(')').count()
+}
+
+fn parse_heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&level) && line[level..].starts_with(' ') {
+        Some((level, line[level..].trim()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders the chunks, returning what was written after each one and at
+    /// the end. Colors are off when not on a terminal, so only the styling
+    /// that changes the text shows.
+    fn render(chunks: &[&str]) -> Vec<String> {
+        let mut renderer = MarkdownRenderer::new().unwrap();
+        let mut out = Vec::new();
+        let mut written = Vec::new();
+        for chunk in chunks {
+            renderer.push(chunk, &mut out).unwrap();
+            written.push(String::from_utf8(out.clone()).unwrap());
+        }
+        renderer.finish(&mut out).unwrap();
+        written.push(String::from_utf8(out).unwrap());
+        written
+    }
+
+    fn rendered(chunks: &[&str]) -> String {
+        render(chunks).pop().unwrap()
+    }
+
+    #[test]
+    fn renders_headings_lists_and_inline_code() {
+        let out = rendered(&[
+            "# Title\n",
+            "- first `item`\n  * nested\n",
+            "Use `cargo test`.\n",
+        ]);
+        assert_eq!(out, "Title\n• first item\n  • nested\nUse cargo test.\n");
+    }
+
+    #[test]
+    fn streams_paragraphs_word_by_word() {
+        let written = render(&["The answer ", "is for", "ty two", ".\n"]);
+        assert_eq!(
+            written,
+            [
+                "The answer ",
+                "The answer is ",
+                "The answer is forty ",
+                "The answer is forty two.\n",
+                "The answer is forty two.\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_for_inline_spans_to_close() {
+        let written = render(&["Run `cargo ", "build` **now", " please** ok"]);
+        assert_eq!(written[0], "Run ");
+        assert_eq!(written[1], "Run cargo build ");
+        assert_eq!(written[2], "Run cargo build now please ");
+        assert_eq!(written[3], "Run cargo build now please ok\n");
+    }
+
+    #[test]
+    fn buffers_lines_that_may_be_markup() {
+        let written = render(&["## Sec", "tion\n"]);
+        assert_eq!(written[0], "");
+        assert_eq!(written[1], "Section\n");
+    }
+
+    #[test]
+    fn highlights_fences_split_across_chunks() {
+        let out = rendered(&["``", "`rust\nfn main() {}\n``", "`\nafter\n"]);
+        let lines: Vec<_> = out.lines().collect();
+
+        assert_eq!(lines[0], "```rust");
+        assert!(
+            lines[1].contains("\x1b[38;2;"),
+            "not highlighted: {:?}",
+            lines[1]
+        );
+        assert!(lines[1].contains("main"));
+        assert_eq!(lines[2], "```");
+        assert_eq!(lines[3], "after");
+    }
+
+    #[test]
+    fn finishes_a_trailing_line_without_newline() {
+        assert_eq!(rendered(&["- last"]), "• last\n");
+        assert_eq!(rendered(&["last line"]), "last line\n");
+    }
+}
@@ -1,22 +1,36 @@
-use crate::Result;
+use crate::{config::Example, image::Image, Result};
 use futures::stream::LocalBoxStream;
 
+/// Settings for a single completion request.
+#[derive(Debug, Clone)]
+pub struct CompletionOptions {
+    pub model: String,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+    /// Ask for a JSON object, using the backend's JSON mode when it has one.
+    pub json_object: bool,
+}
+
 pub trait CompletionGenerator {
     async fn generate_completion(
         &self,
-        model: &str,
-        temperature: f32,
+        options: &CompletionOptions,
         prompt: &str,
+        examples: &[Example],
         input: &str,
+        images: &[Image],
     ) -> Result<String>;
 
+    /// Streams the answer in chunks. A failure ends the stream with an error,
+    /// so an answer that was cut off can be told apart from a complete one.
     async fn stream_completion(
         &self,
-        model: &str,
-        temperature: f32,
+        options: &CompletionOptions,
         prompt: &str,
+        examples: &[Example],
         input: &str,
-    ) -> Result<LocalBoxStream<String>>;
+        images: &[Image],
+    ) -> Result<LocalBoxStream<'_, Result<String>>>;
 }
 
 pub struct CompletionProcessor<T: CompletionGenerator> {
@@ -33,24 +47,26 @@ impl<T: CompletionGenerator> CompletionProcessor<T> {
     pub async fn generate(
         &self,
         prompt: &str,
+        examples: &[Example],
         input: &str,
-        model: &str,
-        temperature: f32,
+        images: &[Image],
+        options: &CompletionOptions,
     ) -> Result<String> {
         self.generator
-            .generate_completion(model, temperature, prompt, input)
+            .generate_completion(options, prompt, examples, input, images)
             .await
     }
 
     pub async fn generate_stream(
         &self,
         prompt: &str,
+        examples: &[Example],
         input: &str,
-        model: &str,
-        temperature: f32,
-    ) -> Result<LocalBoxStream<String>> {
+        images: &[Image],
+        options: &CompletionOptions,
+    ) -> Result<LocalBoxStream<'_, Result<String>>> {
         self.generator
-            .stream_completion(model, temperature, prompt, input)
+            .stream_completion(options, prompt, examples, input, images)
             .await
     }
 }
@@ -0,0 +1,107 @@
+use crate::Result;
+use regex::Regex;
+use serde::Serialize;
+
+/// How much damage running a generated command could do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Risk {
+    Low,
+    /// Changes or removes files, processes or packages, or runs as root.
+    Medium,
+    /// Destroys data in bulk, wipes disks, runs downloaded code or stops
+    /// the machine.
+    High,
+}
+
+const HIGH: &[&str] = &[
+    r"\brm\s+(\S+\s+)*?(-[a-zA-Z]*[rR][a-zA-Z]*|--recursive)(\s|$)",
+    r"\b(mkfs(\.\w+)?|fdisk|parted|wipefs|shred)\b",
+    r"\bdd\b.*\bof=",
+    r">\s*/dev/(sd|hd|nvme|disk|mmcblk)",
+    r":\(\)\s*\{",
+    r"\b(curl|wget)\b[^|]*\|\s*(sudo\s+)?(ba|z)?sh\b",
+    r"\b(shutdown|reboot|halt|poweroff)\b",
+    r"\bgit\s+push\b.*\s(--force|-f)\b",
+];
+
+const MEDIUM: &[&str] = &[
+    r"\bsudo\b",
+    r"\b(rm|rmdir|mv|chmod|chown|truncate|kill|pkill|killall)\b",
+    r"\bgit\s+(reset\s+--hard|clean|rebase|checkout\s+--)\b",
+    r"\b(kubectl|docker|podman)\s+(delete|rm|rmi|kill|(system\s+)?prune)\b",
+    r"\b(apt|apt-get|yum|dnf|pacman|brew|pip3?|npm|cargo)\s+(install|remove|uninstall|purge)\b",
+];
+
+/// A rough guess of how risky a command is, from well-known dangerous
+/// patterns. It helps tools decide whether to ask before running a command,
+/// it doesn't prove one is safe.
+pub fn assess(command: &str) -> Result<Risk> {
+    for (risk, patterns) in [(Risk::High, HIGH), (Risk::Medium, MEDIUM)] {
+        for pattern in patterns {
+            if Regex::new(pattern)?.is_match(command) {
+                return Ok(risk);
+            }
+        }
+    }
+
+    Ok(Risk::Low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_risk(risk: Risk, commands: &[&str]) {
+        for command in commands {
+            assert_eq!(assess(command).unwrap(), risk, "{}", command);
+        }
+    }
+
+    #[test]
+    fn read_only_commands_are_low() {
+        assert_risk(
+            Risk::Low,
+            &[
+                "ls -la /tmp",
+                "grep -rn TODO src | wc -l",
+                "git status && git log --oneline -5",
+                "docker ps",
+                "find . -name '*.rs' -mtime -1",
+            ],
+        );
+    }
+
+    #[test]
+    fn changes_are_medium() {
+        assert_risk(
+            Risk::Medium,
+            &[
+                "rm notes.txt",
+                "mv a.txt b.txt",
+                "sudo systemctl restart nginx",
+                "kill -9 1234",
+                "git reset --hard HEAD~1",
+                "docker rm my-container",
+                "brew install jq",
+            ],
+        );
+    }
+
+    #[test]
+    fn destructive_commands_are_high() {
+        assert_risk(
+            Risk::High,
+            &[
+                "rm -rf ./build",
+                "rm -f -R /var/log/app",
+                "sudo mkfs.ext4 /dev/sdb1",
+                "dd if=/dev/zero of=/dev/sda bs=1M",
+                "curl -fsSL https://example.com/install.sh | sudo bash",
+                ":(){ :|:& };:",
+                "git push -f origin main",
+                "sudo shutdown -h now",
+            ],
+        );
+    }
+}
@@ -2,20 +2,24 @@ use crate::{Error, Result};
 use std::{
     env,
     io::Write,
-    process::{Command, Stdio},
+    process::{Command, ExitStatus, Stdio},
 };
 
-pub fn get_current_shell() -> String {
+pub fn get_current_shell() -> Result<String> {
     if cfg!(target_os = "windows") {
-        todo!("Windows platform is not supported yet.");
+        Err(Error::UnsupportedPlatform {
+            feature: "Running commands".to_string(),
+        })
     } else {
-        env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+        Ok(env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string()))
     }
 }
 
 pub fn copy_to_clipboard(text: &str) -> Result<()> {
     if cfg!(target_os = "windows") {
-        todo!("Windows platform is not supported yet.");
+        Err(Error::UnsupportedPlatform {
+            feature: "Copying to the clipboard".to_string(),
+        })
     } else {
         let tool = if cfg!(target_os = "macos") {
             "pbcopy"
@@ -24,30 +28,55 @@ pub fn copy_to_clipboard(text: &str) -> Result<()> {
         };
 
         // Execute the command
-        Command::new(tool)
-            .stdin(Stdio::piped())
-            .spawn()?
-            .stdin
-            .unwrap()
-            .write_all(text.as_bytes())?;
+        let mut child = Command::new(tool).stdin(Stdio::piped()).spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
 
         Ok(())
     }
 }
 
-pub fn run_cmd(command: &str) -> Result<()> {
-    // Detect the current platform
-    if cfg!(target_os = "windows") {
-        todo!("Windows platform is not supported yet.");
-    } else {
-        let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
-        let status = Command::new(shell).arg("-c").arg(command).status()?;
-        if !status.success() {
-            Err(Error::CommandFailed {
-                command: command.to_string(),
-            })?
+/// Runs `command` in the current shell with its output sent to `stdout`,
+/// returning how it exited.
+pub fn run_cmd_with_stdout(command: &str, stdout: impl Into<Stdio>) -> Result<ExitStatus> {
+    let status = Command::new(get_current_shell()?)
+        .arg("-c")
+        .arg(command)
+        .stdout(stdout)
+        .status()?;
+    Ok(status)
+}
+
+/// The exit code of a finished command, following the shell convention of
+/// 128 plus the signal number for a command killed by a signal.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
         }
+    }
 
-        Ok(())
+    status.code().unwrap_or(1)
+}
+
+/// Runs `command` in the current shell with `input` on its stdin and its
+/// output discarded, returning whether it exited successfully.
+pub fn run_cmd_with_input(command: &str, input: &str) -> Result<bool> {
+    let mut child = Command::new(get_current_shell()?)
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The command may exit without reading all of its input.
+        let _ = stdin.write_all(input.as_bytes());
     }
+
+    Ok(child.wait()?.success())
 }
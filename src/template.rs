@@ -0,0 +1,943 @@
+//! A small template language for prompts.
+//!
+//! - `{name}` inserts a variable, `{name:default}` or `{name:"multi word"}`
+//!   gives it a default. A variable without a default anywhere is required.
+//! - `{count:int=5}`, `{ratio:float=0.5}`, `{debug:bool=false}`,
+//!   `{lang:enum(en|es|fr)=en}` and `{name:string=x}` declare typed variables.
+//!   Leaving the default out, as in `{count:int}`, `{count:int=}` or
+//!   `{lang:enum(en|es)}`, makes them required.
+//! - `{#if name}...{#else}...{/if}` renders a branch depending on whether the
+//!   variable is set to something other than an empty string or `false`.
+//!   `{#if !name}` negates the condition.
+//! - `{#each name as item}...{/each}` repeats the body for every comma
+//!   separated value of `name`, binding it to `item` (`{item}` by default).
+//! - `{> name}` includes the saved prompt `name`, which is resolved by the
+//!   caller with [`Template::expand_includes`].
+//! - `\{` and `\}` produce literal braces. Braces that don't form a valid tag,
+//!   like JSON examples or shell `${VAR}`, are kept as they are.
+
+use crate::{Error, Result};
+use regex::Regex;
+use std::collections::HashMap;
+
+const DEFAULT_BINDING: &str = "item";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    pub default: Option<String>,
+    pub kind: VarType,
+    /// The default is the empty value of a variable only used in `#if` or
+    /// `#each`, not one written in the template.
+    pub implicit: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum VarType {
+    #[default]
+    String,
+    Int,
+    Float,
+    Bool,
+    Enum(Vec<String>),
+}
+
+impl VarType {
+    pub fn accepts(&self, value: &str) -> bool {
+        match self {
+            VarType::String => true,
+            VarType::Int => value.trim().parse::<i64>().is_ok(),
+            VarType::Float => value.trim().parse::<f64>().is_ok(),
+            VarType::Bool => matches!(value, "true" | "false"),
+            VarType::Enum(options) => options.iter().any(|option| option == value),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            VarType::String => "a string".to_string(),
+            VarType::Int => "an integer".to_string(),
+            VarType::Float => "a number".to_string(),
+            VarType::Bool => "true or false".to_string(),
+            VarType::Enum(options) => format!("one of {}", options.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Var {
+        name: String,
+        default: Option<String>,
+        kind: VarType,
+    },
+    If {
+        name: String,
+        negated: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        name: String,
+        binding: String,
+        body: Vec<Node>,
+    },
+    Include(String),
+}
+
+enum Tag {
+    Var {
+        name: String,
+        default: Option<String>,
+        kind: VarType,
+    },
+    If {
+        name: String,
+        negated: bool,
+    },
+    Else,
+    EndIf,
+    Each {
+        name: String,
+        binding: String,
+    },
+    EndEach,
+    Include(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            source,
+            pos: 0,
+            text: String::new(),
+        };
+        let (nodes, end) = parser.parse_nodes()?;
+        if let Some(tag) = end {
+            return Err(invalid(format!("unexpected {}", describe(&tag))));
+        }
+
+        let template = Self { nodes };
+        for variable in template.variables() {
+            match &variable.default {
+                Some(default) if !variable.kind.accepts(default) => {
+                    return Err(invalid(format!(
+                        "default '{}' of '{}' is not {}",
+                        default,
+                        variable.name,
+                        variable.kind.describe()
+                    )))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(template)
+    }
+
+    /// Variables referenced by the template in order of appearance. Loop
+    /// bindings are not included, and variables only used in `#if` or `#each`
+    /// default to an empty value.
+    pub fn variables(&self) -> Vec<Variable> {
+        let mut variables = Vec::new();
+        collect_variables(&self.nodes, &mut Vec::new(), &mut variables);
+        variables
+    }
+
+    /// Names of the prompts included with `{> name}`, in order of appearance.
+    pub fn includes(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        collect_includes(&self.nodes, &mut names);
+        names
+    }
+
+    /// Replaces every `{> name}` with the template returned by `resolve`,
+    /// including the ones inside `#if` and `#each` blocks.
+    pub fn expand_includes(
+        self,
+        resolve: &mut dyn FnMut(&str) -> Result<Template>,
+    ) -> Result<Template> {
+        Ok(Self {
+            nodes: expand_nodes(self.nodes, resolve)?,
+        })
+    }
+
+    /// Adds the nodes of `other` after this template, on a new line.
+    pub fn append(&mut self, other: Template) {
+        let ends_with_newline = match self.nodes.last() {
+            Some(Node::Text(text)) => text.ends_with('\n'),
+            Some(_) => false,
+            None => true,
+        };
+        if !ends_with_newline {
+            self.nodes.push(Node::Text("\n".to_string()));
+        }
+        self.nodes.extend(other.nodes);
+    }
+
+    pub fn render(&self, values: &HashMap<String, String>) -> Result<String> {
+        self.render_with(values, None)
+    }
+
+    /// Renders the template passing every substituted value through `highlight`.
+    pub fn render_highlighted(
+        &self,
+        values: &HashMap<String, String>,
+        highlight: &dyn Fn(&str) -> String,
+    ) -> Result<String> {
+        self.render_with(values, Some(highlight))
+    }
+
+    fn render_with(
+        &self,
+        values: &HashMap<String, String>,
+        highlight: Option<&dyn Fn(&str) -> String>,
+    ) -> Result<String> {
+        let defaults: HashMap<String, String> = self
+            .variables()
+            .into_iter()
+            .filter_map(|v| v.default.map(|d| (v.name, d)))
+            .collect();
+
+        let mut ctx = Context {
+            values,
+            defaults: &defaults,
+            scope: Vec::new(),
+            highlight,
+        };
+        let mut out = String::new();
+        ctx.render_nodes(&self.nodes, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn expand_nodes(
+    nodes: Vec<Node>,
+    resolve: &mut dyn FnMut(&str) -> Result<Template>,
+) -> Result<Vec<Node>> {
+    let mut expanded = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        match node {
+            Node::Include(name) => expanded.extend(resolve(&name)?.nodes),
+            Node::If {
+                name,
+                negated,
+                then,
+                otherwise,
+            } => expanded.push(Node::If {
+                name,
+                negated,
+                then: expand_nodes(then, resolve)?,
+                otherwise: expand_nodes(otherwise, resolve)?,
+            }),
+            Node::Each {
+                name,
+                binding,
+                body,
+            } => expanded.push(Node::Each {
+                name,
+                binding,
+                body: expand_nodes(body, resolve)?,
+            }),
+            node => expanded.push(node),
+        }
+    }
+
+    Ok(expanded)
+}
+
+fn collect_includes<'a>(nodes: &'a [Node], out: &mut Vec<&'a str>) {
+    for node in nodes {
+        match node {
+            Node::Include(name) => out.push(name),
+            Node::If {
+                then, otherwise, ..
+            } => {
+                collect_includes(then, out);
+                collect_includes(otherwise, out);
+            }
+            Node::Each { body, .. } => collect_includes(body, out),
+            Node::Text(_) | Node::Var { .. } => {}
+        }
+    }
+}
+
+fn collect_variables(nodes: &[Node], bindings: &mut Vec<String>, out: &mut Vec<Variable>) {
+    for node in nodes {
+        match node {
+            Node::Text(_) | Node::Include(_) => {}
+            Node::Var {
+                name,
+                default,
+                kind,
+            } => add_variable(out, bindings, name, default.clone(), kind.clone(), false),
+            Node::If {
+                name,
+                then,
+                otherwise,
+                ..
+            } => {
+                add_variable(
+                    out,
+                    bindings,
+                    name,
+                    Some(String::new()),
+                    VarType::String,
+                    true,
+                );
+                collect_variables(then, bindings, out);
+                collect_variables(otherwise, bindings, out);
+            }
+            Node::Each {
+                name,
+                binding,
+                body,
+            } => {
+                add_variable(
+                    out,
+                    bindings,
+                    name,
+                    Some(String::new()),
+                    VarType::String,
+                    true,
+                );
+                bindings.push(binding.clone());
+                collect_variables(body, bindings, out);
+                bindings.pop();
+            }
+        }
+    }
+}
+
+fn add_variable(
+    out: &mut Vec<Variable>,
+    bindings: &[String],
+    name: &str,
+    default: Option<String>,
+    kind: VarType,
+    implicit: bool,
+) {
+    if bindings.iter().any(|b| b == name) {
+        return;
+    }
+
+    match out.iter_mut().find(|v| v.name == name) {
+        Some(existing) => {
+            // A default written in the template wins over an implicit one.
+            if existing.default.is_none() || (existing.implicit && !implicit && default.is_some()) {
+                existing.default = default;
+                existing.implicit = implicit;
+            }
+            if existing.kind == VarType::String {
+                existing.kind = kind;
+            }
+        }
+        None => out.push(Variable {
+            name: name.to_string(),
+            default,
+            kind,
+            implicit,
+        }),
+    }
+}
+
+struct Context<'a> {
+    values: &'a HashMap<String, String>,
+    defaults: &'a HashMap<String, String>,
+    scope: Vec<(String, String)>,
+    highlight: Option<&'a dyn Fn(&str) -> String>,
+}
+
+impl Context<'_> {
+    fn lookup(&self, name: &str) -> Option<&str> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(binding, _)| binding == name)
+            .map(|(_, value)| value.as_str())
+            .or_else(|| self.values.get(name).map(String::as_str))
+    }
+
+    fn render_nodes(&mut self, nodes: &[Node], out: &mut String) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Var { name, default, .. } => {
+                    let value = self
+                        .lookup(name)
+                        .or(default.as_deref())
+                        .or_else(|| self.defaults.get(name).map(String::as_str))
+                        .ok_or_else(|| Error::MissingPromptValue { name: name.clone() })?;
+                    match self.highlight {
+                        Some(highlight) => out.push_str(&highlight(value)),
+                        None => out.push_str(value),
+                    }
+                }
+                Node::If {
+                    name,
+                    negated,
+                    then,
+                    otherwise,
+                } => {
+                    let value = self.lookup(name).unwrap_or_default();
+                    let truthy = !value.is_empty() && value != "false";
+                    if truthy != *negated {
+                        self.render_nodes(then, out)?;
+                    } else {
+                        self.render_nodes(otherwise, out)?;
+                    }
+                }
+                Node::Each {
+                    name,
+                    binding,
+                    body,
+                } => {
+                    let value = self.lookup(name).unwrap_or_default().to_string();
+                    for item in split_list(&value) {
+                        self.scope.push((binding.clone(), item.to_string()));
+                        let result = self.render_nodes(body, out);
+                        self.scope.pop();
+                        result?;
+                    }
+                }
+                Node::Include(name) => {
+                    return Err(invalid(format!("prompt '{}' was not included", name)))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts the content of a prompt written for the first template syntax,
+/// which only had `{name:default}` tags, so that any other brace still
+/// renders literally. Defaults are quoted so `{count:int}` stays a default.
+pub fn upgrade_legacy(content: &str) -> Result<String> {
+    let tag = Regex::new(r"\{(\w+):(\w+)\}")?;
+    let escape = |text: &str| text.replace('{', "\\{").replace('}', "\\}");
+
+    let mut upgraded = String::new();
+    let mut last = 0;
+    for captures in tag.captures_iter(content) {
+        let m = captures.get(0).map_or(0..0, |m| m.range());
+        upgraded.push_str(&escape(&content[last..m.start]));
+        upgraded.push_str(&format!("{{{}:\"{}\"}}", &captures[1], &captures[2]));
+        last = m.end;
+    }
+    upgraded.push_str(&escape(&content[last..]));
+
+    Ok(upgraded)
+}
+
+/// Splits a list value like `en, es, fr` into its items.
+pub fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+    text: String,
+}
+
+impl Parser<'_> {
+    /// Parses nodes until the end of the input or a closing tag, which is
+    /// returned so the caller can check it matches what it opened.
+    fn parse_nodes(&mut self) -> Result<(Vec<Node>, Option<Tag>)> {
+        let mut nodes = Vec::new();
+
+        while let Some(c) = self.source[self.pos..].chars().next() {
+            match c {
+                '\\' if matches!(self.peek_at(1), Some('{') | Some('}')) => {
+                    self.text.push(self.peek_at(1).unwrap_or_default());
+                    self.pos += 2;
+                }
+                '{' if !self.text.ends_with('$') => match self.parse_tag() {
+                    Some((tag, len)) => {
+                        self.pos += len;
+                        self.flush_text(&mut nodes);
+                        match tag {
+                            Tag::Var {
+                                name,
+                                default,
+                                kind,
+                            } => nodes.push(Node::Var {
+                                name,
+                                default,
+                                kind,
+                            }),
+                            Tag::If { name, negated } => nodes.push(self.parse_if(name, negated)?),
+                            Tag::Each { name, binding } => {
+                                nodes.push(self.parse_each(name, binding)?)
+                            }
+                            Tag::Include(name) => nodes.push(Node::Include(name)),
+                            closing => return Ok((nodes, Some(closing))),
+                        }
+                    }
+                    None => {
+                        self.text.push(c);
+                        self.pos += 1;
+                    }
+                },
+                _ => {
+                    self.text.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+
+        self.flush_text(&mut nodes);
+        Ok((nodes, None))
+    }
+
+    fn parse_if(&mut self, name: String, negated: bool) -> Result<Node> {
+        let (then, end) = self.parse_nodes()?;
+        let otherwise = match end {
+            Some(Tag::EndIf) => Vec::new(),
+            Some(Tag::Else) => match self.parse_nodes()? {
+                (otherwise, Some(Tag::EndIf)) => otherwise,
+                (_, end) => return Err(unclosed("#if", &name, end)),
+            },
+            end => return Err(unclosed("#if", &name, end)),
+        };
+
+        Ok(Node::If {
+            name,
+            negated,
+            then,
+            otherwise,
+        })
+    }
+
+    fn parse_each(&mut self, name: String, binding: String) -> Result<Node> {
+        match self.parse_nodes()? {
+            (body, Some(Tag::EndEach)) => Ok(Node::Each {
+                name,
+                binding,
+                body,
+            }),
+            (_, end) => Err(unclosed("#each", &name, end)),
+        }
+    }
+
+    fn flush_text(&mut self, nodes: &mut Vec<Node>) {
+        if !self.text.is_empty() {
+            nodes.push(Node::Text(std::mem::take(&mut self.text)));
+        }
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.source[self.pos..].chars().nth(offset)
+    }
+
+    /// Tries to read a tag starting at the current `{`, returning it with its
+    /// length in bytes. Anything that isn't a valid tag yields `None`.
+    fn parse_tag(&self) -> Option<(Tag, usize)> {
+        let rest = &self.source[self.pos + 1..];
+        let (inner, len) = tag_body(rest)?;
+        let inner_trimmed = inner.trim();
+
+        let tag = if let Some(cond) = inner_trimmed.strip_prefix("#if ") {
+            let cond = cond.trim();
+            let (negated, name) = match cond.strip_prefix('!') {
+                Some(name) => (true, name.trim()),
+                None => (false, cond),
+            };
+            Tag::If {
+                name: valid_name(name)?.to_string(),
+                negated,
+            }
+        } else if inner_trimmed == "#else" {
+            Tag::Else
+        } else if inner_trimmed == "/if" {
+            Tag::EndIf
+        } else if let Some(spec) = inner_trimmed.strip_prefix("#each ") {
+            let mut parts = spec.split_whitespace();
+            let name = valid_name(parts.next()?)?.to_string();
+            let binding = match (parts.next(), parts.next(), parts.next()) {
+                (None, _, _) => DEFAULT_BINDING.to_string(),
+                (Some("as"), Some(binding), None) => valid_name(binding)?.to_string(),
+                _ => return None,
+            };
+            Tag::Each { name, binding }
+        } else if inner_trimmed == "/each" {
+            Tag::EndEach
+        } else if let Some(name) = inner_trimmed.strip_prefix('>') {
+            let name = name.trim();
+            if !is_valid_prompt_name(name) {
+                return None;
+            }
+            Tag::Include(name.to_string())
+        } else {
+            let (name, (kind, default)) = match inner.split_once(':') {
+                // Whitespace around the `:` is prose or JSON, like `{status: ok}`.
+                Some((_, spec)) if spec.trim() != spec => return None,
+                Some((name, spec)) => (name, parse_spec(spec)?),
+                None => (inner, (VarType::String, None)),
+            };
+            Tag::Var {
+                name: valid_name(name)?.to_string(),
+                default,
+                kind,
+            }
+        };
+
+        Some((tag, len + 1))
+    }
+}
+
+/// Returns the content between `{` and the matching `}`, skipping over
+/// quoted strings, and the length including the closing brace.
+fn tag_body(rest: &str) -> Option<(&str, usize)> {
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in rest.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '{' | '\n' if !in_quotes => return None,
+            '}' if !in_quotes => return Some((&rest[..i], i + 1)),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Parses what follows `:` in a variable tag, either a type with an optional
+/// default (`int=5`, `int`, `enum(en|es)`) or a plain default as in
+/// `{to:spanish}`.
+fn parse_spec(spec: &str) -> Option<(VarType, Option<String>)> {
+    let (kind, rest) = if let Some(rest) = spec.strip_prefix("enum(") {
+        let (options, rest) = rest.split_once(')')?;
+        let options: Vec<String> = options
+            .split('|')
+            .map(|option| option.trim().to_string())
+            .filter(|option| !option.is_empty())
+            .collect();
+        if options.is_empty() || !(rest.is_empty() || rest.starts_with('=')) {
+            return None;
+        }
+        (VarType::Enum(options), rest.strip_prefix('='))
+    } else {
+        let (kind, rest) = match spec.split_once('=') {
+            Some((kind, rest)) => (kind, Some(rest)),
+            None => (spec, None),
+        };
+        let typed = match kind {
+            "string" => Some(VarType::String),
+            "int" => Some(VarType::Int),
+            "float" => Some(VarType::Float),
+            "bool" => Some(VarType::Bool),
+            _ => None,
+        }
+        .map(|kind| (kind, rest));
+
+        match typed {
+            Some(typed) => typed,
+            None => return Some((VarType::String, Some(parse_default(spec)?))),
+        }
+    };
+
+    let default = match rest {
+        Some(raw) if !raw.is_empty() => Some(parse_default(raw)?),
+        _ => None,
+    };
+    Some((kind, default))
+}
+
+fn parse_default(raw: &str) -> Option<String> {
+    match raw.strip_prefix('"') {
+        Some(quoted) => {
+            let quoted = quoted.strip_suffix('"')?;
+            Some(quoted.replace("\\\"", "\"").replace("\\\\", "\\"))
+        }
+        None if raw.contains('"') => None,
+        None => Some(raw.to_string()),
+    }
+}
+
+fn valid_name(name: &str) -> Option<&str> {
+    let mut chars = name.chars();
+    let first = chars.next()?;
+    let valid = (first.is_alphabetic() || first == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.');
+    valid.then_some(name)
+}
+
+/// Prompt names are file names, so they can also contain dashes, but nothing
+/// that would let them point outside the prompts directory.
+pub fn is_valid_prompt_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn describe(tag: &Tag) -> &'static str {
+    match tag {
+        Tag::Else => "{#else}",
+        Tag::EndIf => "{/if}",
+        Tag::EndEach => "{/each}",
+        _ => "tag",
+    }
+}
+
+fn unclosed(block: &str, name: &str, end: Option<Tag>) -> Error {
+    match end {
+        Some(tag) => invalid(format!(
+            "unexpected {} inside {{{} {}}}",
+            describe(&tag),
+            block,
+            name
+        )),
+        None => invalid(format!("{{{} {}}} is never closed", block, name)),
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidTemplate { reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, values: &[(&str, &str)]) -> Result<String> {
+        let values = values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Template::parse(source)?.render(&values)
+    }
+
+    fn reason(result: Result<impl std::fmt::Debug>) -> String {
+        match result {
+            Err(Error::InvalidTemplate { reason }) => reason,
+            other => panic!("expected an invalid template, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let out = render("Translate {text} to {to}", &[("text", "hi"), ("to", "es")]);
+        assert_eq!(out.unwrap(), "Translate hi to es");
+    }
+
+    #[test]
+    fn missing_value_is_an_error() {
+        let result = render("Hello {name}", &[]);
+        assert!(matches!(result, Err(Error::MissingPromptValue { ref name }) if name == "name"));
+    }
+
+    #[test]
+    fn uses_defaults() {
+        assert_eq!(render("to {to:spanish}", &[]).unwrap(), "to spanish");
+        assert_eq!(
+            render("to {to:\"old english\"}", &[]).unwrap(),
+            "to old english"
+        );
+        assert_eq!(
+            render("to {to:spanish}", &[("to", "french")]).unwrap(),
+            "to french"
+        );
+    }
+
+    #[test]
+    fn default_applies_to_every_use() {
+        assert_eq!(render("{lang} {lang:en} {lang}", &[]).unwrap(), "en en en");
+    }
+
+    #[test]
+    fn parses_typed_variables() {
+        let template =
+            Template::parse("{count:int=5} {ratio:float=} {lang:enum(en|es)=es}").unwrap();
+
+        assert_eq!(
+            template.variables(),
+            [
+                Variable {
+                    name: "count".to_string(),
+                    default: Some("5".to_string()),
+                    kind: VarType::Int,
+                    implicit: false,
+                },
+                Variable {
+                    name: "ratio".to_string(),
+                    default: None,
+                    kind: VarType::Float,
+                    implicit: false,
+                },
+                Variable {
+                    name: "lang".to_string(),
+                    default: Some("es".to_string()),
+                    kind: VarType::Enum(vec!["en".to_string(), "es".to_string()]),
+                    implicit: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bare_type_is_a_required_variable() {
+        for (source, kind) in [
+            ("{count:int}", VarType::Int),
+            ("{count:int=}", VarType::Int),
+            ("{ratio:float}", VarType::Float),
+            ("{flag:bool}", VarType::Bool),
+            ("{name:string}", VarType::String),
+        ] {
+            let variables = Template::parse(source).unwrap().variables();
+            assert_eq!(variables[0].default, None, "{}", source);
+            assert_eq!(variables[0].kind, kind, "{}", source);
+        }
+
+        let result = render("{flag:bool}", &[]);
+        assert!(matches!(result, Err(Error::MissingPromptValue { ref name }) if name == "flag"));
+    }
+
+    #[test]
+    fn rejects_default_of_the_wrong_type() {
+        let reason = reason(Template::parse("{count:int=many}"));
+        assert_eq!(reason, "default 'many' of 'count' is not an integer");
+    }
+
+    #[test]
+    fn renders_conditionals() {
+        let source = "{#if verbose}long{#else}short{/if}{#if !verbose}!{/if}";
+        assert_eq!(render(source, &[("verbose", "yes")]).unwrap(), "long");
+        assert_eq!(render(source, &[("verbose", "false")]).unwrap(), "short!");
+        assert_eq!(render(source, &[]).unwrap(), "short!");
+    }
+
+    #[test]
+    fn conditional_only_variables_have_an_implicit_default() {
+        let variables = Template::parse("{#if debug}{lang}{/if}{#each tags}{/each}{lang:en}")
+            .unwrap()
+            .variables();
+
+        let defaults: Vec<_> = variables
+            .iter()
+            .map(|v| (v.name.as_str(), v.default.as_deref(), v.implicit))
+            .collect();
+        assert_eq!(
+            defaults,
+            [
+                ("debug", Some(""), true),
+                ("lang", Some("en"), false),
+                ("tags", Some(""), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn explicit_default_wins_over_implicit_one() {
+        let variables = Template::parse("{#if lang}{lang:en}{/if}")
+            .unwrap()
+            .variables();
+        assert_eq!(variables[0].default.as_deref(), Some("en"));
+        assert!(!variables[0].implicit);
+    }
+
+    #[test]
+    fn renders_loops() {
+        let source = "{#each langs as lang}[{lang}]{/each}{#each tags}<{item}>{/each}";
+        let out = render(source, &[("langs", "en, es,,fr"), ("tags", "a")]);
+        assert_eq!(out.unwrap(), "[en][es][fr]<a>");
+    }
+
+    #[test]
+    fn loop_binding_is_not_a_variable() {
+        let template = Template::parse("{#each langs as lang}{lang}{/each}").unwrap();
+        let names: Vec<_> = template.variables().into_iter().map(|v| v.name).collect();
+        assert_eq!(names, ["langs"]);
+    }
+
+    #[test]
+    fn keeps_braces_that_are_not_tags() {
+        let source = r#"echo ${HOME} {"key": 1} \{name\}"#;
+        assert_eq!(
+            render(source, &[]).unwrap(),
+            r#"echo ${HOME} {"key": 1} {name}"#
+        );
+    }
+
+    #[test]
+    fn keeps_braces_with_spaced_colons() {
+        let source = "Reply as JSON like {status: ok} or {count: int}, {a :b}";
+        assert_eq!(render(source, &[]).unwrap(), source);
+        assert!(Template::parse(source).unwrap().variables().is_empty());
+    }
+
+    #[test]
+    fn upgrades_legacy_content() {
+        let legacy = r#"Translate to {to:spanish} {n:int} times: {x} {"a": 1} ${HOME}"#;
+        let upgraded = upgrade_legacy(legacy).unwrap();
+
+        let variables: Vec<_> = Template::parse(&upgraded)
+            .unwrap()
+            .variables()
+            .into_iter()
+            .map(|v| (v.name, v.default, v.kind))
+            .collect();
+        assert_eq!(
+            variables,
+            [
+                (
+                    "to".to_string(),
+                    Some("spanish".to_string()),
+                    VarType::String
+                ),
+                ("n".to_string(), Some("int".to_string()), VarType::String),
+            ]
+        );
+        assert_eq!(
+            render(&upgraded, &[]).unwrap(),
+            r#"Translate to spanish int times: {x} {"a": 1} ${HOME}"#
+        );
+    }
+
+    #[test]
+    fn lists_includes() {
+        let template =
+            Template::parse("{> a} {#if x}{> b}{#else}{#each xs}{> c}{/each}{/if}").unwrap();
+        assert_eq!(template.includes(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn expands_includes() {
+        let template = Template::parse("{#if x}{> footer}{/if}").unwrap();
+        let template = template
+            .expand_includes(&mut |name| Template::parse(&format!("<{}:{{x}}>", name)))
+            .unwrap();
+
+        let values = HashMap::from([("x".to_string(), "1".to_string())]);
+        assert_eq!(template.render(&values).unwrap(), "<footer:1>");
+    }
+
+    #[test]
+    fn reports_unbalanced_blocks() {
+        assert_eq!(
+            reason(Template::parse("{#if x}a")),
+            "{#if x} is never closed"
+        );
+        assert_eq!(
+            reason(Template::parse("{#each xs}a{/if}")),
+            "unexpected {/if} inside {#each xs}"
+        );
+        assert_eq!(reason(Template::parse("a{/each}")), "unexpected {/each}");
+    }
+}